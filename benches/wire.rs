@@ -0,0 +1,186 @@
+use rayzo::client::Client;
+use rayzo::resources::Resources;
+use rayzo::server::Server;
+use rayzo::SynchronizeOutbound;
+use serde::{Deserialize, Serialize};
+use serde_diff::{Diff, SerdeDiff};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[derive(Debug, Clone, Serialize, Deserialize, SerdeDiff)]
+struct Transform {
+    position: [f32; 3],
+    rotation: [f32; 4],
+    velocity: [f32; 3],
+}
+
+#[derive(Default)]
+struct Sink {
+    bytes: usize,
+}
+
+impl SynchronizeOutbound<usize> for Sink {
+    fn synchronize(&mut self, _bound: usize, data: &[u8]) {
+        self.bytes += data.len();
+    }
+}
+
+#[derive(Serialize)]
+enum LegacySerializedResource {
+    Diff(Vec<u8>),
+    Full(Vec<u8>),
+}
+
+#[derive(Serialize)]
+struct LegacyResourcePacket {
+    identifier: String,
+    data: Vec<u8>,
+}
+
+fn legacy_payload(previous: &Transform, current: &Transform) -> Vec<u8> {
+    let diff = Diff::serializable(previous, current);
+    let diff = rmp_serde::to_vec(&diff).unwrap();
+    let full = rmp_serde::to_vec(current).unwrap();
+    let data = if full.len() > diff.len() {
+        rmp_serde::to_vec(&LegacySerializedResource::Diff(diff)).unwrap()
+    } else {
+        rmp_serde::to_vec(&LegacySerializedResource::Full(full)).unwrap()
+    };
+    let packet = LegacyResourcePacket {
+        identifier: "player_transform_42".into(),
+        data,
+    };
+    bincode::serialize(&packet).unwrap()
+}
+
+// Keeps the packets so they can be handed to the other side.
+#[derive(Default)]
+struct Outbox {
+    packets: Vec<Vec<u8>>,
+}
+
+impl<B> SynchronizeOutbound<B> for Outbox {
+    fn synchronize(&mut self, _bound: B, data: &[u8]) {
+        self.packets.push(data.to_vec());
+    }
+}
+
+// Acknowledgements a lossless client sends back, one per tick.
+fn acks(initial: &Transform, ticks: usize) -> Vec<Vec<u8>> {
+    let mut server = Server::<Outbox, usize>::new(Outbox::default());
+    server
+        .resources_mut()
+        .register_outbound("player_transform_42".into(), initial.clone());
+    let mut client = Client::<Outbox, ()>::new(Outbox::default(), ());
+    client
+        .resources_mut()
+        .register_inbound("player_transform_42".into(), initial.clone());
+    client.handshake();
+    for packet in client.packets.drain(..) {
+        server.synchronize_inbound(0, packet);
+    }
+
+    let mut acks = Vec::new();
+    for tick in 0..=ticks {
+        if tick > 0 {
+            let transform = server
+                .resources_mut()
+                .outbound_mut::<Transform>("player_transform_42".into())
+                .unwrap();
+            step(transform, tick - 1);
+        }
+        server.synchronize_outbound();
+        for packet in server.packets.drain(..) {
+            client.synchronize_inbound(packet);
+        }
+        client.synchronize_outbound();
+        if tick > 0 {
+            acks.append(&mut client.packets);
+        }
+        for packet in client.packets.drain(..) {
+            server.synchronize_inbound(0, packet);
+        }
+    }
+    assert_eq!(acks.len(), ticks);
+    acks
+}
+
+fn step(transform: &mut Transform, tick: usize) {
+    transform.position[0] += 0.1;
+    transform.rotation[3] = (tick as f32 * 0.01).cos();
+}
+
+fn main() {
+    let ticks = 10_000;
+    let initial = Transform {
+        position: [0.0; 3],
+        rotation: [0.0, 0.0, 0.0, 1.0],
+        velocity: [1.0, 0.0, 0.0],
+    };
+
+    let mut previous = initial.clone();
+    let mut current = initial.clone();
+    let mut legacy_bytes = 0;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for tick in 0..ticks {
+        step(&mut current, tick);
+        legacy_bytes += legacy_payload(&previous, &current).len();
+        previous = current.clone();
+    }
+    let legacy_time = start.elapsed();
+    let legacy_allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    let mut server = Server::<Sink, usize>::new(Sink::default());
+    server.register_connection(0);
+    server
+        .resources_mut()
+        .register_outbound("player_transform_42".into(), initial.clone());
+    server.synchronize_outbound();
+    server.bytes = 0;
+    let mut acks = acks(&initial, ticks).into_iter();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for tick in 0..ticks {
+        let transform = server
+            .resources_mut()
+            .outbound_mut::<Transform>("player_transform_42".into())
+            .unwrap();
+        step(transform, tick);
+        server.synchronize_outbound();
+        server.synchronize_inbound(0, acks.next().unwrap());
+    }
+    let time = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let bytes = server.bytes;
+
+    println!("{} ticks of a single Transform resource", ticks);
+    println!(
+        "legacy: {:>8} bytes {:>8} allocations {:?}",
+        legacy_bytes, legacy_allocations, legacy_time
+    );
+    println!(
+        "wire:   {:>8} bytes {:>8} allocations {:?}",
+        bytes, allocations, time
+    );
+}
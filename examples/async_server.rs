@@ -0,0 +1,48 @@
+use rayzo::resources::Resources;
+use rayzo::server::{Server, ServerEvent};
+use rayzo::tokio::{ServerEvents, ServerHandle, UdpTransport};
+use serde::{Deserialize, Serialize};
+use serde_diff::SerdeDiff;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize, SerdeDiff)]
+struct Count(usize);
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    println!("Starting counting server...");
+
+    let transport = UdpTransport::bind("127.0.0.1:12346").await.unwrap();
+    let mut server = Server::<UdpTransport, SocketAddr>::new(transport);
+    server
+        .resources_mut()
+        .register_outbound("count".into(), Count(0));
+
+    let (sender, mut events) = ServerEvents::channel();
+    tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            match event {
+                ServerEvent::Connected(address) => println!("{} connected", address),
+                ServerEvent::Disconnected(address) => println!("{} disconnected", address),
+                ServerEvent::HandshakeRejected(address, error) => {
+                    println!("{} rejected: {}", address, error)
+                }
+                _ => {}
+            }
+        }
+    });
+
+    // Kept to reach the server from other tasks, for instance to reset the count.
+    let (_handle, commands) = ServerHandle::channel();
+    server
+        .run(Duration::from_secs(1), sender, commands, |server| {
+            let count = server
+                .resources_mut()
+                .outbound_mut::<Count>("count".into())
+                .unwrap();
+            count.0 += 1;
+        })
+        .await;
+}
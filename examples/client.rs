@@ -1,5 +1,4 @@
 use laminar::Config;
-use laminar::Packet;
 use laminar::Socket;
 use laminar::SocketEvent;
 use rayzo::client::Client;
@@ -41,23 +40,13 @@ fn main() {
     let _thread = thread::spawn(move || loop {
         if let Ok(event) = event_receiver.recv() {
             let mut client = client_2.lock().unwrap();
-            match event {
-                SocketEvent::Packet(packet) => {
-                    client.synchronize_inbound(packet.payload().to_vec());
-                }
-                _ => {}
+            if let SocketEvent::Packet(packet) = event {
+                client.synchronize_inbound(packet.payload().to_vec());
             }
         }
     });
 
-    client
-        .lock()
-        .unwrap()
-        .send(Packet::reliable_unordered(
-            "127.0.0.1:12346".parse().unwrap(),
-            b"".to_vec(),
-        ))
-        .unwrap();
+    client.lock().unwrap().handshake();
 
     let mut should_exit = false;
     let mut previous_instant = Instant::now();
@@ -1,9 +1,8 @@
 use laminar::Config;
-use laminar::Packet;
 use laminar::Socket;
 use laminar::SocketEvent;
 use rayzo::resources::Resources;
-use rayzo::server::Server;
+use rayzo::server::{Server, ServerEvent};
 use serde::{Deserialize, Serialize};
 use serde_diff::SerdeDiff;
 
@@ -26,7 +25,7 @@ fn main() {
         ..Config::default()
     };
 
-    let server = Arc::new(Mutex::new(Server::new(
+    let server = Arc::new(Mutex::new(Server::<Socket, SocketAddr>::new(
         Socket::bind_with_config("127.0.0.1:12346", config).unwrap(),
     )));
     let event_receiver = server.lock().unwrap().get_event_receiver().clone();
@@ -42,16 +41,7 @@ fn main() {
             let mut server = server_2.lock().unwrap();
             match event {
                 SocketEvent::Packet(packet) => {
-                    if server.is_connected(packet.addr()) {
-                        server.synchronize_inbound(packet.addr(), packet.payload().to_vec());
-                    } else {
-                        server
-                            .send(Packet::reliable_unordered(packet.addr(), b"".to_vec()))
-                            .unwrap();
-                    }
-                }
-                SocketEvent::Connect(address) => {
-                    server.register_connection(address);
+                    server.synchronize_inbound(packet.addr(), packet.payload().to_vec());
                 }
                 SocketEvent::Timeout(address) => {
                     server.remove_connection(address);
@@ -71,6 +61,15 @@ fn main() {
             .as_secs_f64();
         if elapsed >= fixed_time_step {
             let mut server = server.lock().unwrap();
+            for event in server.drain_events() {
+                match event {
+                    ServerEvent::Connected(address) => println!("{} connected", address),
+                    ServerEvent::HandshakeRejected(address, error) => {
+                        println!("{} rejected: {}", address, error)
+                    }
+                    _ => {}
+                }
+            }
             should_exit = update(&mut server);
 
             server.synchronize_outbound();
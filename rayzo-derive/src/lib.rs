@@ -0,0 +1,422 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, LitStr, Type, Visibility};
+
+struct Field {
+    ident: Ident,
+    ty: Type,
+    vis: Visibility,
+    quantize: Option<Type>,
+    visibility: Ident,
+    interpolate: bool,
+}
+
+impl Field {
+    fn name(&self) -> String {
+        self.ident.to_string()
+    }
+
+    fn wire_type(&self) -> TokenStream2 {
+        let ty = &self.ty;
+        match &self.quantize {
+            Some(precision) => quote!(<#ty as ::rayzo::quantize::Quantize<#precision>>::Wire),
+            None => quote!(#ty),
+        }
+    }
+
+    fn wire_value(&self, value: TokenStream2) -> TokenStream2 {
+        let ty = &self.ty;
+        match &self.quantize {
+            Some(precision) => {
+                quote!(&<#ty as ::rayzo::quantize::Quantize<#precision>>::quantize(&#value))
+            }
+            None => quote!(&#value),
+        }
+    }
+
+    fn field_value(&self, wire: TokenStream2) -> TokenStream2 {
+        let ty = &self.ty;
+        match &self.quantize {
+            Some(precision) => {
+                quote!(<#ty as ::rayzo::quantize::Quantize<#precision>>::dequantize(#wire))
+            }
+            None => wire,
+        }
+    }
+}
+
+// Fields that aren't synchronized and are rebuilt with `Default` on the receiving end.
+struct Local {
+    ident: Ident,
+    changes: bool,
+}
+
+#[proc_macro_derive(Networked, attributes(rayzo, networked))]
+pub fn derive_networked(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match networked(input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn networked(input: DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "`Networked` can't be derived for generic types",
+        ));
+    }
+    let named = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new_spanned(&input, "`Networked` needs named fields")),
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input,
+                "`Networked` only supports structs",
+            ))
+        }
+    };
+
+    let mut fields = Vec::new();
+    let mut locals = Vec::new();
+    for field in named {
+        let ident = field.ident.clone().unwrap();
+        let mut skip = false;
+        let mut quantize = None;
+        let mut visibility = "Everyone";
+        let mut interpolate = false;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("rayzo"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                } else if meta.path.is_ident("quantize") {
+                    quantize = Some(meta.value()?.parse::<LitStr>()?.parse::<Type>()?);
+                } else if meta.path.is_ident("owner_only") {
+                    visibility = "Owner";
+                } else if meta.path.is_ident("server_only") {
+                    visibility = "Server";
+                } else if meta.path.is_ident("interpolate") {
+                    interpolate = true;
+                } else {
+                    return Err(meta.error("unknown rayzo attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        // The field holding the `Changes` of the other fields.
+        let mut changes = false;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("networked"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("changes") {
+                    changes = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown networked attribute"))
+                }
+            })?;
+        }
+        if changes && locals.iter().any(|local: &Local| local.changes) {
+            return Err(Error::new_spanned(
+                field,
+                "only one field can hold the changes",
+            ));
+        }
+        if skip || changes {
+            locals.push(Local { ident, changes });
+        } else {
+            fields.push(Field {
+                ident,
+                ty: field.ty.clone(),
+                vis: field.vis.clone(),
+                quantize,
+                visibility: Ident::new(visibility, Span::call_site()),
+                interpolate,
+            });
+        }
+    }
+
+    let name = &input.ident;
+    let changes = locals.iter().find(|local| local.changes).map(|l| &l.ident);
+    let serde = serde(name, &fields, &locals);
+    let tracked = changes.map(|changes| tracked(name, changes, &fields));
+    let interpolate = interpolate(name, &fields, &locals);
+
+    let descriptions = fields.iter().map(|field| {
+        let field_name = field.name();
+        let visibility = &field.visibility;
+        let interpolate = field.interpolate;
+        quote! {
+            ::rayzo::resources::Field {
+                name: #field_name,
+                visibility: ::rayzo::resources::Visibility::#visibility,
+                interpolate: #interpolate,
+            }
+        }
+    });
+    let projections = fields
+        .iter()
+        .filter(|field| field.visibility != "Everyone")
+        .map(|field| {
+            let ident = &field.ident;
+            let visibility = &field.visibility;
+            quote! {
+                if !::rayzo::resources::Visibility::#visibility.is_visible_to(recipient) {
+                    self.#ident = ::std::default::Default::default();
+                }
+            }
+        });
+    let strategy = match changes {
+        Some(_) => quote!(::rayzo::tracked::TrackedDelta::<__K>::with_codec()),
+        None => quote!(::rayzo::diff::XorDelta::<__K>::with_codec()),
+    };
+
+    Ok(quote! {
+        #serde
+        #tracked
+        #interpolate
+
+        impl ::rayzo::resources::NetworkedResource for #name {
+            const FIELDS: &'static [::rayzo::resources::Field] = &[#(#descriptions),*];
+
+            fn strategy<__K: ::rayzo::codec::Codec>(
+            ) -> ::std::boxed::Box<dyn ::rayzo::diff::DiffStrategy<Self>> {
+                ::std::boxed::Box::new(#strategy)
+            }
+
+            #[allow(unused_variables)]
+            fn project(&mut self, recipient: ::rayzo::resources::Recipient) {
+                #(#projections)*
+            }
+        }
+    })
+}
+
+// Structs are written as a sequence of their synchronized fields, quantized ones in their wire
+// form. Maps are accepted too for self-describing codecs.
+fn serde(name: &Ident, fields: &[Field], locals: &[Local]) -> TokenStream2 {
+    let name_str = name.to_string();
+    let len = fields.len();
+    let names = fields.iter().map(Field::name).collect::<Vec<_>>();
+    let idents = fields.iter().map(|f| &f.ident).collect::<Vec<_>>();
+    let locals = locals.iter().map(|l| &l.ident).collect::<Vec<_>>();
+    let bindings = (0..len)
+        .map(|i| format_ident!("__field{}", i))
+        .collect::<Vec<_>>();
+    let types = fields.iter().map(|f| &f.ty).collect::<Vec<_>>();
+
+    let serialize_fields = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let field_name = field.name();
+        let value = field.wire_value(quote!(self.#ident));
+        quote!(::rayzo::serde::ser::SerializeStruct::serialize_field(&mut state, #field_name, #value)?;)
+    });
+    let seq_fields = fields.iter().zip(&bindings).enumerate().map(|(i, (field, binding))| {
+        let wire_type = field.wire_type();
+        let value = field.field_value(quote!(value));
+        quote! {
+            let #binding = match ::rayzo::serde::de::SeqAccess::next_element::<#wire_type>(&mut seq)? {
+                ::std::option::Option::Some(value) => #value,
+                ::std::option::Option::None => {
+                    return ::std::result::Result::Err(
+                        ::rayzo::serde::de::Error::invalid_length(#i, &self),
+                    )
+                }
+            };
+        }
+    });
+    let map_fields = fields.iter().zip(&bindings).map(|(field, binding)| {
+        let field_name = field.name();
+        let wire_type = field.wire_type();
+        let value = field.field_value(quote!(value));
+        quote! {
+            #field_name => {
+                let value = ::rayzo::serde::de::MapAccess::next_value::<#wire_type>(&mut map)?;
+                #binding = ::std::option::Option::Some(#value);
+            }
+        }
+    });
+    let expecting = format!("struct {}", name_str);
+    let visitor = Ident::new("__Visitor", Span::call_site());
+
+    quote! {
+        impl ::rayzo::serde::Serialize for #name {
+            fn serialize<__S: ::rayzo::serde::Serializer>(
+                &self,
+                serializer: __S,
+            ) -> ::std::result::Result<__S::Ok, __S::Error> {
+                let mut state = serializer.serialize_struct(#name_str, #len)?;
+                #(#serialize_fields)*
+                ::rayzo::serde::ser::SerializeStruct::end(state)
+            }
+        }
+
+        impl<'de> ::rayzo::serde::Deserialize<'de> for #name {
+            fn deserialize<__D: ::rayzo::serde::Deserializer<'de>>(
+                deserializer: __D,
+            ) -> ::std::result::Result<Self, __D::Error> {
+                struct #visitor;
+
+                impl<'de> ::rayzo::serde::de::Visitor<'de> for #visitor {
+                    type Value = #name;
+
+                    fn expecting(
+                        &self,
+                        formatter: &mut ::std::fmt::Formatter<'_>,
+                    ) -> ::std::fmt::Result {
+                        formatter.write_str(#expecting)
+                    }
+
+                    fn visit_seq<__A: ::rayzo::serde::de::SeqAccess<'de>>(
+                        self,
+                        mut seq: __A,
+                    ) -> ::std::result::Result<Self::Value, __A::Error> {
+                        #(#seq_fields)*
+                        ::std::result::Result::Ok(#name {
+                            #(#idents: #bindings,)*
+                            #(#locals: ::std::default::Default::default(),)*
+                        })
+                    }
+
+                    fn visit_map<__A: ::rayzo::serde::de::MapAccess<'de>>(
+                        self,
+                        mut map: __A,
+                    ) -> ::std::result::Result<Self::Value, __A::Error> {
+                        #(let mut #bindings: ::std::option::Option<#types> = ::std::option::Option::None;)*
+                        while let ::std::option::Option::Some(key) =
+                            ::rayzo::serde::de::MapAccess::next_key::<::std::string::String>(&mut map)?
+                        {
+                            match key.as_str() {
+                                #(#map_fields)*
+                                _ => {
+                                    ::rayzo::serde::de::MapAccess::next_value::<
+                                        ::rayzo::serde::de::IgnoredAny,
+                                    >(&mut map)?;
+                                }
+                            }
+                        }
+                        ::std::result::Result::Ok(#name {
+                            #(#idents: #bindings.ok_or_else(|| {
+                                ::rayzo::serde::de::Error::missing_field(#names)
+                            })?,)*
+                            #(#locals: ::std::default::Default::default(),)*
+                        })
+                    }
+                }
+
+                deserializer.deserialize_struct(#name_str, &[#(#names),*], #visitor)
+            }
+        }
+    }
+}
+
+// Setters record the write in the `Changes` field, the field index being its position among
+// the synchronized fields.
+fn tracked(name: &Ident, changes: &Ident, fields: &[Field]) -> TokenStream2 {
+    let indices = 0..fields.len();
+    let write_fields = fields.iter().map(|field| {
+        let ident = &field.ident;
+        field.wire_value(quote!(self.#ident))
+    });
+    let read_fields = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let wire_type = field.wire_type();
+        let value = field.field_value(quote!(__K::deserialize::<#wire_type>(data)?));
+        quote!(self.#ident = #value)
+    });
+    let accessors = fields.iter().enumerate().map(|(i, field)| {
+        let ident = &field.ident;
+        let ty = &field.ty;
+        let vis = &field.vis;
+        let setter = format_ident!("set_{}", ident);
+        let getter = format_ident!("{}_mut", ident);
+        quote! {
+            #vis fn #setter(&mut self, value: #ty) {
+                self.#ident = value;
+                self.#changes.write(#i);
+            }
+
+            #vis fn #getter(&mut self) -> &mut #ty {
+                self.#changes.write(#i);
+                &mut self.#ident
+            }
+        }
+    });
+    let read_indices = indices.clone();
+    let count = fields.len();
+
+    quote! {
+        impl ::rayzo::tracked::Tracked for #name {
+            const FIELD_COUNT: usize = #count;
+
+            fn changes(&self) -> &::rayzo::tracked::Changes {
+                &self.#changes
+            }
+
+            fn write_field<__K: ::rayzo::codec::Codec>(
+                &self,
+                field: usize,
+                buffer: &mut ::std::vec::Vec<u8>,
+            ) -> ::std::option::Option<()> {
+                match field {
+                    #(#indices => __K::serialize_into(#write_fields, buffer),)*
+                    _ => ::std::option::Option::None,
+                }
+            }
+
+            fn read_field<__K: ::rayzo::codec::Codec>(
+                &mut self,
+                field: usize,
+                data: &[u8],
+            ) -> ::std::option::Option<()> {
+                match field {
+                    #(#read_indices => #read_fields,)*
+                    _ => return ::std::option::Option::None,
+                }
+                ::std::option::Option::Some(())
+            }
+        }
+
+        impl #name {
+            #(#accessors)*
+        }
+    }
+}
+
+// Interpolated fields are blended, the others take the newer value.
+fn interpolate(name: &Ident, fields: &[Field], locals: &[Local]) -> Option<TokenStream2> {
+    if !fields.iter().any(|field| field.interpolate) {
+        return None;
+    }
+    let values = fields.iter().map(|field| {
+        let ident = &field.ident;
+        if field.interpolate {
+            quote!(#ident: ::rayzo::interpolation::Interpolate::interpolate(&self.#ident, &other.#ident, t))
+        } else {
+            quote!(#ident: ::std::clone::Clone::clone(&other.#ident))
+        }
+    });
+    let locals = locals.iter().map(|l| &l.ident).collect::<Vec<_>>();
+
+    Some(quote! {
+        impl ::rayzo::interpolation::Interpolate for #name {
+            fn interpolate(&self, other: &Self, t: f32) -> Self {
+                #name {
+                    #(#values,)*
+                    #(#locals: ::std::clone::Clone::clone(&other.#locals),)*
+                }
+            }
+        }
+    })
+}
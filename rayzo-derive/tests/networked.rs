@@ -0,0 +1,120 @@
+use rayzo::codec::{Codec, DefaultCodec};
+use rayzo::diff::DiffStrategy;
+use rayzo::interpolation::Interpolate;
+use rayzo::quantize::FloatRange;
+use rayzo::resources::{NetworkedResource, Recipient, Visibility};
+use rayzo::tracked::{Changes, Tracked};
+use rayzo::Networked;
+
+struct Meters;
+
+impl FloatRange for Meters {
+    const MIN: f32 = -100.0;
+    const MAX: f32 = 100.0;
+    const PRECISION: f32 = 0.01;
+}
+
+#[derive(Debug, Clone, Default, Networked)]
+struct Player {
+    #[rayzo(quantize = "Meters", interpolate)]
+    position: [f32; 2],
+    name: String,
+    #[rayzo(owner_only)]
+    ammunition: u32,
+    #[rayzo(server_only)]
+    cheats: bool,
+    #[rayzo(skip)]
+    cache: Vec<u8>,
+    #[networked(changes)]
+    changes: Changes,
+}
+
+#[derive(Debug, Clone, PartialEq, Networked)]
+struct Plain {
+    score: i64,
+}
+
+fn player() -> Player {
+    Player {
+        position: [1.234, -5.678],
+        name: "player".into(),
+        ammunition: 30,
+        cheats: true,
+        cache: vec![1, 2, 3],
+        changes: Changes::default(),
+    }
+}
+
+#[test]
+fn fields_are_described() {
+    let fields = Player::FIELDS
+        .iter()
+        .map(|field| (field.name, field.visibility, field.interpolate))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        vec![
+            ("position", Visibility::Everyone, true),
+            ("name", Visibility::Everyone, false),
+            ("ammunition", Visibility::Owner, false),
+            ("cheats", Visibility::Server, false),
+        ]
+    );
+    assert_eq!(<Player as Tracked>::FIELD_COUNT, 4);
+}
+
+#[test]
+fn quantized_fields_round_trip_and_local_ones_are_rebuilt() {
+    let data = DefaultCodec::serialize(&player()).unwrap();
+    let received = DefaultCodec::deserialize::<Player>(&data).unwrap();
+    assert!((received.position[0] - 1.23).abs() < 1e-4);
+    assert!((received.position[1] + 5.68).abs() < 1e-4);
+    assert_eq!(received.name, "player");
+    assert_eq!(received.ammunition, 30);
+    assert!(received.cache.is_empty());
+
+    let plain = Plain { score: -3 };
+    let data = DefaultCodec::serialize(&plain).unwrap();
+    assert_eq!(DefaultCodec::deserialize::<Plain>(&data), Some(plain));
+}
+
+#[test]
+fn projections_hide_fields_from_other_recipients() {
+    let mut others = player();
+    others.project(Recipient::Others);
+    assert_eq!((others.ammunition, others.cheats), (0, false));
+    let mut owner = player();
+    owner.project(Recipient::Owner);
+    assert_eq!((owner.ammunition, owner.cheats), (30, false));
+    let mut server = player();
+    server.project(Recipient::Server);
+    assert_eq!((server.ammunition, server.cheats), (30, true));
+}
+
+#[test]
+fn setters_record_changes() {
+    let old = player();
+    let mut new = old.clone();
+    new.set_ammunition(29);
+    *new.name_mut() = "renamed".into();
+    let written = new.changes().since(old.changes().version());
+    assert_eq!(written.collect::<Vec<_>>(), vec![1, 2]);
+
+    let mut strategy = Player::strategy::<DefaultCodec>();
+    let mut buffer = Vec::new();
+    assert_eq!(strategy.diff(&old, &new, &mut buffer), Some(true));
+    let mut target = old.clone();
+    strategy.apply(&buffer, &mut target).unwrap();
+    assert_eq!((target.name.as_str(), target.ammunition), ("renamed", 29));
+    assert_eq!(target.position, old.position);
+}
+
+#[test]
+fn interpolated_fields_are_blended() {
+    let mut next = player();
+    next.position = [3.0, 5.0];
+    next.name = "next".into();
+    let halfway = player().interpolate(&next, 0.5);
+    assert!((halfway.position[0] - 2.115).abs() < 0.01);
+    assert_eq!(halfway.name, "next");
+}
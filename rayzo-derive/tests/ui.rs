@@ -0,0 +1,4 @@
+#[test]
+fn invalid_input_is_reported() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,9 @@
+use rayzo::Networked;
+
+#[derive(Debug, Clone, Networked)]
+enum State {
+    Idle,
+    Moving,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use rayzo::Networked;
+
+#[derive(Debug, Clone, Networked)]
+struct Generic<T> {
+    value: T,
+}
+
+fn main() {}
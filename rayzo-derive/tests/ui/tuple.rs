@@ -0,0 +1,6 @@
+use rayzo::Networked;
+
+#[derive(Debug, Clone, Networked)]
+struct Tuple(u32, u32);
+
+fn main() {}
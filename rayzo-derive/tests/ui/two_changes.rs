@@ -0,0 +1,13 @@
+use rayzo::tracked::Changes;
+use rayzo::Networked;
+
+#[derive(Debug, Clone, Networked)]
+struct Player {
+    health: u32,
+    #[networked(changes)]
+    first: Changes,
+    #[networked(changes)]
+    second: Changes,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use rayzo::Networked;
+
+#[derive(Debug, Clone, Networked)]
+struct Player {
+    #[rayzo(hidden)]
+    health: u32,
+}
+
+fn main() {}
@@ -0,0 +1,478 @@
+use crate::client::Client;
+use crate::codec::Codec;
+use crate::collections::{OperationLog, SyncMap};
+use crate::resources::{NetworkedResource, Resources};
+use crate::server::{OutboundIdentifier, Server};
+use crate::transport::Listen;
+use crate::{SynchronizeInbound, SynchronizeOutbound, Target};
+use bevy_app::{App, FixedPostUpdate, FixedPreUpdate, Plugin, Startup};
+use bevy_ecs::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+// The server sends replicated state and the client receives it. Both are stored as non-send
+// resources since validators and handlers aren't `Sync`.
+pub trait Endpoint: 'static {
+    // Runs at startup, clients open the connection with the handshake.
+    fn start(&mut self) {}
+
+    fn receive_inbound(&mut self);
+
+    fn synchronize_outbound(&mut self);
+
+    fn register_replicated<T: NetworkedResource>(&mut self, identifier: &str, value: T);
+
+    fn register_replicated_map<T: Replicate>(&mut self, identifier: &str);
+
+    fn replicated<T: Replicate>(&self, identifier: &str) -> Option<(usize, &T)>;
+
+    fn replicated_mut<T: Replicate>(&mut self, identifier: &str) -> Option<&mut T>;
+}
+
+pub trait Replicate: 'static + Debug + Clone + Serialize + DeserializeOwned + Send + Sync {}
+
+impl<T> Replicate for T where T: 'static + Debug + Clone + Serialize + DeserializeOwned + Send + Sync
+{}
+
+impl<S, C, K> Endpoint for Server<S, C, K>
+where
+    S: 'static + SynchronizeOutbound<C> + Listen<C>,
+    C: 'static + Eq + Hash + Clone + Send,
+    K: Codec,
+{
+    fn receive_inbound(&mut self) {
+        self.poll_connections();
+    }
+
+    fn synchronize_outbound(&mut self) {
+        Server::synchronize_outbound(self);
+    }
+
+    fn register_replicated<T: NetworkedResource>(&mut self, identifier: &str, value: T) {
+        self.resources_mut()
+            .register_networked_outbound(identifier.into(), value);
+    }
+
+    fn register_replicated_map<T: Replicate>(&mut self, identifier: &str) {
+        let strategy = OperationLog::<K>::with_codec();
+        self.resources_mut().register_outbound_with(
+            identifier.into(),
+            SyncMap::<u64, T>::new(),
+            strategy,
+        );
+    }
+
+    fn replicated<T: Replicate>(&self, _identifier: &str) -> Option<(usize, &T)> {
+        None
+    }
+
+    fn replicated_mut<T: Replicate>(&mut self, identifier: &str) -> Option<&mut T> {
+        let identifier = OutboundIdentifier(identifier.to_string(), Target::All);
+        let resource = self.resources_mut().outbound_mut::<T>(identifier)?;
+        Some(&mut **resource)
+    }
+}
+
+impl<C, S, K> Endpoint for Client<C, S, K>
+where
+    C: 'static + SynchronizeOutbound<S> + SynchronizeInbound<S>,
+    S: 'static + Clone + PartialEq,
+    K: Codec,
+{
+    fn start(&mut self) {
+        self.handshake();
+    }
+
+    fn receive_inbound(&mut self) {
+        Client::receive_inbound(self);
+    }
+
+    fn synchronize_outbound(&mut self) {
+        Client::synchronize_outbound(self);
+    }
+
+    fn register_replicated<T: NetworkedResource>(&mut self, identifier: &str, value: T) {
+        self.resources_mut()
+            .register_networked_inbound(identifier.into(), value);
+    }
+
+    fn register_replicated_map<T: Replicate>(&mut self, identifier: &str) {
+        let strategy = OperationLog::<K>::with_codec();
+        self.resources_mut().register_inbound_with(
+            identifier.into(),
+            SyncMap::<u64, T>::new(),
+            strategy,
+        );
+    }
+
+    fn replicated<T: Replicate>(&self, identifier: &str) -> Option<(usize, &T)> {
+        let resource = self.resources().inbound::<T>(identifier.into())?;
+        Some((resource.generation()?, &**resource))
+    }
+
+    fn replicated_mut<T: Replicate>(&mut self, _identifier: &str) -> Option<&mut T> {
+        None
+    }
+}
+
+// Entities with this marker have their replicated components sent, and entities spawned for
+// received components get it too.
+#[derive(Debug, Clone, Copy, Default, Component)]
+pub struct Replicated;
+
+// Maps the sender's entities to the local ones, along with how many of their replicated
+// components were received. Entities are despawned when the last one is removed, which is how
+// despawns on the sender end up being replicated.
+#[derive(Debug, Default, Resource)]
+pub struct ReplicatedEntities {
+    entities: HashMap<u64, (Entity, usize)>,
+}
+
+impl ReplicatedEntities {
+    pub fn get(&self, key: u64) -> Option<Entity> {
+        self.entities.get(&key).map(|(entity, _)| *entity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    fn add(&mut self, key: u64, commands: &mut Commands) -> Entity {
+        let (entity, count) = self
+            .entities
+            .entry(key)
+            .or_insert_with(|| (commands.spawn(Replicated).id(), 0));
+        *count += 1;
+        *entity
+    }
+
+    // Also returns whether it was the last component of the entity.
+    fn remove(&mut self, key: u64) -> Option<(Entity, bool)> {
+        let (entity, count) = self.entities.get_mut(&key)?;
+        *count -= 1;
+        let removed = (*entity, *count == 0);
+        if removed.1 {
+            self.entities.remove(&key);
+        }
+        Some(removed)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemSet)]
+pub enum RayzoSet {
+    Receive,
+    Read,
+    Write,
+    Send,
+}
+
+#[derive(Resource)]
+struct Replication<T: 'static> {
+    identifier: String,
+    generation: Option<usize>,
+    keys: HashSet<u64>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Replication<T> {
+    fn new(identifier: &str) -> Self {
+        Self {
+            identifier: identifier.into(),
+            generation: None,
+            keys: HashSet::new(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+type Registration = Box<dyn Fn(&mut App) + Send + Sync>;
+
+// Clients connect at `Startup`, received state is applied in `FixedPreUpdate` and changes are
+// sent in `FixedPostUpdate`. Components are replicated as one map per component type, keyed by
+// entity.
+pub struct RayzoPlugin<E: Endpoint> {
+    endpoint: Mutex<Option<E>>,
+    registrations: Vec<Registration>,
+}
+
+impl<E: Endpoint + Send> RayzoPlugin<E> {
+    pub fn new(endpoint: E) -> Self {
+        Self {
+            endpoint: Mutex::new(Some(endpoint)),
+            registrations: Vec::new(),
+        }
+    }
+
+    pub fn replicate_resource<T>(mut self, identifier: &str) -> Self
+    where
+        T: NetworkedResource + Resource + Default,
+    {
+        let identifier = identifier.to_string();
+        self.registrations.push(Box::new(move |app| {
+            let world = app.world_mut();
+            world
+                .non_send_resource_mut::<E>()
+                .register_replicated(&identifier, T::default());
+            app.insert_resource(Replication::<T>::new(&identifier))
+                .add_systems(FixedPreUpdate, read_resource::<E, T>.in_set(RayzoSet::Read))
+                .add_systems(
+                    FixedPostUpdate,
+                    write_resource::<E, T>.in_set(RayzoSet::Write),
+                );
+        }));
+        self
+    }
+
+    pub fn replicate_component<T>(mut self, identifier: &str) -> Self
+    where
+        T: Replicate + Component,
+    {
+        let identifier = identifier.to_string();
+        self.registrations.push(Box::new(move |app| {
+            let world = app.world_mut();
+            world
+                .non_send_resource_mut::<E>()
+                .register_replicated_map::<T>(&identifier);
+            app.insert_resource(Replication::<T>::new(&identifier))
+                .add_systems(
+                    FixedPreUpdate,
+                    read_components::<E, T>.in_set(RayzoSet::Read),
+                )
+                .add_systems(
+                    FixedPostUpdate,
+                    write_components::<E, T>.in_set(RayzoSet::Write),
+                );
+        }));
+        self
+    }
+}
+
+impl<E: Endpoint + Send> Plugin for RayzoPlugin<E> {
+    fn build(&self, app: &mut App) {
+        if let Some(endpoint) = self.endpoint.lock().unwrap().take() {
+            app.insert_non_send_resource(endpoint);
+        }
+        app.init_resource::<ReplicatedEntities>()
+            .add_systems(Startup, start::<E>)
+            .configure_sets(FixedPreUpdate, (RayzoSet::Receive, RayzoSet::Read).chain())
+            .configure_sets(FixedPostUpdate, (RayzoSet::Write, RayzoSet::Send).chain())
+            .add_systems(FixedPreUpdate, receive::<E>.in_set(RayzoSet::Receive))
+            .add_systems(FixedPostUpdate, send::<E>.in_set(RayzoSet::Send));
+        for registration in &self.registrations {
+            registration(app);
+        }
+    }
+}
+
+fn start<E: Endpoint>(mut endpoint: NonSendMut<E>) {
+    endpoint.start();
+}
+
+fn receive<E: Endpoint>(mut endpoint: NonSendMut<E>) {
+    endpoint.receive_inbound();
+}
+
+fn send<E: Endpoint>(mut endpoint: NonSendMut<E>) {
+    endpoint.synchronize_outbound();
+}
+
+fn read_resource<E, T>(
+    endpoint: NonSend<E>,
+    mut replication: ResMut<Replication<T>>,
+    mut commands: Commands,
+) where
+    E: Endpoint,
+    T: NetworkedResource + Resource,
+{
+    if let Some((generation, value)) = endpoint.replicated::<T>(&replication.identifier) {
+        if replication.generation != Some(generation) {
+            replication.generation = Some(generation);
+            commands.insert_resource(value.clone());
+        }
+    }
+}
+
+// Only resources changed since the last run are marked dirty.
+fn write_resource<E, T>(
+    mut endpoint: NonSendMut<E>,
+    replication: Res<Replication<T>>,
+    resource: Option<Res<T>>,
+) where
+    E: Endpoint,
+    T: NetworkedResource + Resource,
+{
+    if let Some(resource) = resource.filter(|resource| resource.is_changed()) {
+        if let Some(replicated) = endpoint.replicated_mut::<T>(&replication.identifier) {
+            *replicated = T::clone(&resource);
+        }
+    }
+}
+
+fn read_components<E, T>(
+    endpoint: NonSend<E>,
+    mut replication: ResMut<Replication<T>>,
+    mut entities: ResMut<ReplicatedEntities>,
+    mut commands: Commands,
+) where
+    E: Endpoint,
+    T: Replicate + Component,
+{
+    let map = endpoint.replicated::<SyncMap<u64, T>>(&replication.identifier);
+    let (generation, map) = match map {
+        Some((generation, map)) if replication.generation != Some(generation) => (generation, map),
+        _ => return,
+    };
+    replication.generation = Some(generation);
+    for (key, component) in map.iter() {
+        let entity = match entities.get(*key) {
+            Some(entity) if replication.keys.contains(key) => entity,
+            _ => entities.add(*key, &mut commands),
+        };
+        if let Ok(mut entity) = commands.get_entity(entity) {
+            entity.insert(component.clone());
+        }
+    }
+    for key in replication.keys.iter().filter(|key| !map.contains_key(key)) {
+        if let Some((entity, last)) = entities.remove(*key) {
+            if let Ok(mut entity) = commands.get_entity(entity) {
+                if last {
+                    entity.despawn();
+                } else {
+                    entity.remove::<T>();
+                }
+            }
+        }
+    }
+    replication.keys = map.keys().copied().collect();
+}
+
+fn write_components<E, T>(
+    mut endpoint: NonSendMut<E>,
+    replication: Res<Replication<T>>,
+    components: Query<(Entity, Ref<T>), With<Replicated>>,
+    mut removed: RemovedComponents<T>,
+) where
+    E: Endpoint,
+    T: Replicate + Component,
+{
+    let mut changed = components
+        .iter()
+        .filter(|(_, component)| component.is_changed())
+        .peekable();
+    let removed = removed.read().collect::<Vec<_>>();
+    if changed.peek().is_none() && removed.is_empty() {
+        return;
+    }
+    let map = endpoint.replicated_mut::<SyncMap<u64, T>>(&replication.identifier);
+    if let Some(map) = map {
+        for (entity, component) in changed {
+            map.insert(entity.to_bits(), T::clone(&component));
+        }
+        for entity in removed {
+            map.remove(&entity.to_bits());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{DiffStrategy, XorDelta};
+    use crate::resources::{Field, Recipient};
+    use crate::testing::{self, TestClient, TestServer};
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Component, Serialize, Deserialize)]
+    struct Position(i32);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Component, Serialize, Deserialize)]
+    struct Health(u32);
+
+    #[derive(Debug, Clone, Default, PartialEq, Resource, Serialize, Deserialize)]
+    struct Score(u32);
+
+    impl NetworkedResource for Score {
+        const FIELDS: &'static [Field] = &[];
+
+        fn strategy<K: Codec>() -> Box<dyn DiffStrategy<Self>> {
+            Box::new(XorDelta::<K>::with_codec())
+        }
+
+        fn project(&mut self, _recipient: Recipient) {}
+    }
+
+    fn app<E: Endpoint + Send>(endpoint: E) -> App {
+        let plugin = RayzoPlugin::new(endpoint)
+            .replicate_resource::<Score>("score")
+            .replicate_component::<Position>("position")
+            .replicate_component::<Health>("health");
+        let mut app = App::new();
+        app.add_plugins(plugin);
+        app.world_mut().run_schedule(Startup);
+        app
+    }
+
+    // The fixed schedules are run by hand since there is no time plugin.
+    fn tick(server: &mut App, client: &mut App) {
+        for app in [server, client].iter_mut() {
+            app.world_mut().run_schedule(FixedPreUpdate);
+            app.world_mut().run_schedule(FixedPostUpdate);
+        }
+    }
+
+    fn received<T: Component + Copy>(client: &mut App) -> Vec<T> {
+        let world = client.world_mut();
+        let mut query = world.query_filtered::<&T, With<Replicated>>();
+        query.iter(world).copied().collect()
+    }
+
+    #[test]
+    fn worlds_are_replicated() {
+        let mut server = testing::server();
+        let client: TestClient = testing::client(&mut server);
+        let mut server = app::<TestServer>(server);
+        let mut client = app(client);
+        tick(&mut server, &mut client);
+        assert!(client
+            .world()
+            .non_send_resource::<TestClient>()
+            .is_connected());
+
+        server.world_mut().insert_resource(Score(3));
+        let entity = server
+            .world_mut()
+            .spawn((Replicated, Position(1), Health(10)))
+            .id();
+        server.world_mut().spawn((Replicated, Position(2)));
+        tick(&mut server, &mut client);
+        tick(&mut server, &mut client);
+        assert_eq!(client.world().get_resource::<Score>(), Some(&Score(3)));
+        let mut positions = received::<Position>(&mut client);
+        positions.sort_by_key(|position| position.0);
+        assert_eq!(positions, vec![Position(1), Position(2)]);
+        assert_eq!(received::<Health>(&mut client), vec![Health(10)]);
+
+        server.world_mut().entity_mut(entity).remove::<Health>();
+        server.world_mut().get_mut::<Position>(entity).unwrap().0 = 5;
+        tick(&mut server, &mut client);
+        tick(&mut server, &mut client);
+        assert!(received::<Health>(&mut client).is_empty());
+        assert!(received::<Position>(&mut client).contains(&Position(5)));
+
+        server.world_mut().despawn(entity);
+        tick(&mut server, &mut client);
+        tick(&mut server, &mut client);
+        assert_eq!(received::<Position>(&mut client), vec![Position(2)]);
+        assert_eq!(client.world().resource::<ReplicatedEntities>().len(), 1);
+        let world = client.world_mut();
+        assert_eq!(world.query::<&Replicated>().iter(world).count(), 1);
+    }
+}
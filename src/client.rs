@@ -1,34 +1,59 @@
+use crate::codec::{Codec, DefaultCodec};
+use crate::compression::{Compression, Compressions};
+use crate::handshake::{ConnectionState, HandshakeResponse, Hello, Ids};
+use crate::messages::{MessageError, Messages};
 use crate::node::Node;
-use crate::resources::HashMapResources;
-use crate::{ResourcePacket, SynchronizeOutbound};
+use crate::resources::{HashMapResources, Recipient};
+use crate::rpc::{Call, CallError, Calls};
+use crate::wire::write_ack;
+use crate::{PacketKind, Reliability, ResourcePacket, SynchronizeInbound, SynchronizeOutbound};
+use instant::Instant;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
 use std::ops::Deref;
 use std::ops::DerefMut;
+use std::ops::Range;
+use std::time::Duration;
 
 #[allow(dead_code)]
-pub struct Client<C, S>
+pub struct Client<C, S, K = DefaultCodec>
 where
     C: SynchronizeOutbound<S>,
     S: Clone,
+    K: Codec,
 {
     pub(crate) server: S,
+    pub(crate) application_id: String,
+    pub(crate) state: ConnectionState,
+    pub(crate) messages: Messages<(), K>,
+    // Bodies only, the header is written with the server's id once connected.
+    pub(crate) outbound_messages: Vec<(PacketKind, u32, Range<usize>, Reliability)>,
+    pub(crate) message_buffer: Vec<u8>,
+    pub(crate) calls: Calls<K>,
+    pub(crate) acks: Vec<(u32, usize)>,
+    pub(crate) nacks: Vec<String>,
+    pub(crate) remote: Ids,
+    pub(crate) buffer: Vec<u8>,
+    pub(crate) compressions: Compressions<()>,
     pub(crate) node: Node<
         C,
         InboundIdentifier,
         OutboundIdentifier,
-        HashMapResources<InboundIdentifier, OutboundIdentifier>,
+        HashMapResources<InboundIdentifier, OutboundIdentifier, K>,
     >,
 }
 
-impl<C, S> Deref for Client<C, S>
+impl<C, S, K> Deref for Client<C, S, K>
 where
     C: SynchronizeOutbound<S>,
     S: Clone,
+    K: Codec,
 {
     type Target = Node<
         C,
         InboundIdentifier,
         OutboundIdentifier,
-        HashMapResources<InboundIdentifier, OutboundIdentifier>,
+        HashMapResources<InboundIdentifier, OutboundIdentifier, K>,
     >;
 
     fn deref(&self) -> &Self::Target {
@@ -36,57 +61,262 @@ where
     }
 }
 
-impl<C, S> DerefMut for Client<C, S>
+impl<C, S, K> DerefMut for Client<C, S, K>
 where
     C: SynchronizeOutbound<S>,
     S: Clone,
+    K: Codec,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.node
     }
 }
 
-impl<C, S> Client<C, S>
+impl<C, S, K> Client<C, S, K>
 where
     C: SynchronizeOutbound<S>,
     S: Clone,
+    K: Codec,
 {
     pub fn new(node: C, server: S) -> Self {
         Self {
             server,
+            application_id: String::new(),
+            state: ConnectionState::Disconnected,
+            messages: Messages::default(),
+            outbound_messages: Vec::new(),
+            message_buffer: Vec::new(),
+            calls: Calls::default(),
+            acks: Vec::new(),
+            nacks: Vec::new(),
+            remote: Ids::default(),
+            buffer: Vec::new(),
+            compressions: Compressions::default(),
             node: Node::new(node),
         }
     }
 
+    pub fn application_id(&self) -> &str {
+        &self.application_id
+    }
+
+    pub fn set_application_id(&mut self, application_id: String) {
+        self.application_id = application_id;
+    }
+
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state == ConnectionState::Connected
+    }
+
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compressions.default = compression;
+    }
+
+    pub fn handshake(&mut self) {
+        for resource in self.node.resources.inbound_resources.values_mut() {
+            resource.reset();
+        }
+        self.acks.clear();
+        self.nacks.clear();
+        let hello = Hello::new(&self.application_id, self.node.resources.schema());
+        let mut payload = Vec::new();
+        ResourcePacket::write::<K, _>(PacketKind::Handshake, 0, &hello, &mut payload);
+        self.node.node.synchronize_with(
+            self.server.clone(),
+            &payload,
+            Reliability::ReliableUnordered,
+        );
+        self.state = ConnectionState::Connecting;
+    }
+
+    pub fn register_message<T>(&mut self, identifier: String, reliability: Reliability)
+    where
+        T: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        self.node.resources.schema.declare::<T>(&identifier);
+        self.messages.register::<T>(identifier, reliability);
+    }
+
+    pub fn send_message<T>(&mut self, message: T) -> Result<(), MessageError>
+    where
+        T: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        let (identifier, reliability) = self.messages.identifier::<T>()?;
+        let id = self.node.resources.schema.id(identifier).unwrap();
+        let buffer = &mut self.message_buffer;
+        let start = buffer.len();
+        K::serialize_into(&message, buffer).unwrap();
+        self.outbound_messages
+            .push((PacketKind::Message, id, start..buffer.len(), reliability));
+        Ok(())
+    }
+
+    pub fn drain_messages<T>(&mut self) -> impl Iterator<Item = T> + '_
+    where
+        T: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        self.messages.drain::<T>().map(|(_, message)| message)
+    }
+
+    pub fn register_call<Req, Resp>(&mut self, identifier: String, timeout: Duration)
+    where
+        Req: 'static + Debug + Serialize + DeserializeOwned + Send,
+        Resp: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        self.node
+            .resources
+            .schema
+            .declare::<(Req, Resp)>(&identifier);
+        self.calls.register::<Req, Resp>(identifier, timeout);
+    }
+
+    pub fn call<Req, Resp>(&mut self, request: Req) -> Call<Resp>
+    where
+        Req: 'static + Debug + Serialize + DeserializeOwned + Send,
+        Resp: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        let (id, identifier, call) = match self.calls.call::<Req, Resp>() {
+            Ok(call) => call,
+            Err(error) => return Call::failed(error),
+        };
+        let identifier = self.node.resources.schema.id(identifier).unwrap();
+        let kind = PacketKind::Request(id);
+        let buffer = &mut self.message_buffer;
+        let start = buffer.len();
+        K::serialize_into(&request, buffer).unwrap();
+        let range = start..buffer.len();
+        self.outbound_messages
+            .push((kind, identifier, range, Reliability::ReliableUnordered));
+        call
+    }
+
+    pub fn disconnect(&mut self) {
+        self.state = ConnectionState::Disconnected;
+        self.outbound_messages.clear();
+        self.message_buffer.clear();
+        self.calls.fail_all(CallError::Disconnected);
+    }
+
     pub fn synchronize_outbound(&mut self) {
+        self.calls.expire(Instant::now());
+        if !self.is_connected() {
+            return;
+        }
+
         let resources = &mut self.node.resources.outbound_resources;
+        let schema = &self.node.resources.schema;
+        let remote = &self.remote;
         let server = &self.server;
         let node = &mut self.node.node;
+        let buffer = &mut self.buffer;
+        let compressions = &mut self.compressions;
+
+        for (kind, id, range, reliability) in self.outbound_messages.drain(..) {
+            let id = match schema.identifier(id).and_then(|name| remote.id(name)) {
+                Some(id) => id,
+                None => continue,
+            };
+            buffer.clear();
+            ResourcePacket::write_header(kind, id, buffer);
+            buffer.extend_from_slice(&self.message_buffer[range]);
+            let payload = compressions.compress(&(), buffer);
+            node.synchronize_with(server.clone(), payload, reliability);
+        }
+        self.message_buffer.clear();
+
+        for (id, generation) in self.acks.drain(..) {
+            buffer.clear();
+            write_ack(id, generation, buffer);
+            let payload = compressions.compress(&(), buffer);
+            node.synchronize_with(server.clone(), payload, Reliability::Unreliable);
+        }
 
+        let nacks = &mut self.nacks;
         for (identifier, resource) in resources.iter_mut() {
-            if resource.is_dirty() {
-                let generation = resource.generation();
-                let packet = ResourcePacket {
-                    identifier: identifier.clone(),
-                    data: resource.serialize(generation.is_none()).unwrap(),
-                };
-                let payload = bincode::serialize(&packet).unwrap();
-                node.synchronize(server.clone(), payload.clone());
+            let nacked = nacks.contains(identifier);
+            let id = match remote.id(identifier) {
+                Some(id) => id,
+                None => continue,
+            };
+            if nacked || resource.is_dirty() {
+                let generation = if nacked { None } else { resource.generation() };
+                buffer.clear();
+                ResourcePacket::write_header(PacketKind::Resource, id, buffer);
+                if resource.serialize(generation, Recipient::Server, buffer) {
+                    node.synchronize(server.clone(), compressions.compress(&(), buffer));
+                }
                 resource.snapshot();
                 resource.set_dirty(false);
             }
         }
+        nacks.clear();
+    }
+
+    // Packets that don't come from the server are dropped.
+    pub fn receive_inbound(&mut self)
+    where
+        C: SynchronizeInbound<S>,
+        S: PartialEq,
+    {
+        while let Some((source, data)) = self.node.node.receive() {
+            if source == self.server {
+                self.synchronize_inbound(data);
+            }
+        }
     }
 
     pub fn synchronize_inbound(&mut self, data: Vec<u8>) {
-        let packet = bincode::deserialize::<ResourcePacket>(&data);
-        if let Ok(packet) = packet {
-            self.node
-                .resources_mut()
-                .inbound_resources
-                .get_mut(&packet.identifier)
-                .unwrap()
-                .deserialize(&packet.data);
+        let data = match self.compressions.decompress(&(), data) {
+            Some(data) => data,
+            None => return,
+        };
+        let packet = ResourcePacket::read(&data);
+        if let Some(packet) = packet {
+            match packet.kind {
+                PacketKind::Handshake => {
+                    match K::deserialize::<HandshakeResponse>(packet.data) {
+                        Some(HandshakeResponse::Accepted(identifiers)) => {
+                            self.remote = Ids::from(identifiers);
+                            self.state = ConnectionState::Connected
+                        }
+                        Some(HandshakeResponse::Rejected(error)) => {
+                            self.disconnect();
+                            self.state = ConnectionState::Rejected(error);
+                        }
+                        None => {}
+                    }
+                    return;
+                }
+                PacketKind::Resource => {}
+                PacketKind::Message => {
+                    if let Some(name) = self.remote.identifier(packet.identifier) {
+                        self.messages.deserialize((), name, packet.data);
+                    }
+                    return;
+                }
+                PacketKind::Response(id) => {
+                    self.calls.resolve(id, packet.data);
+                    return;
+                }
+                PacketKind::Nack => {
+                    if let Some(name) = self.remote.identifier(packet.identifier) {
+                        self.nacks.push(name.to_string());
+                    }
+                    return;
+                }
+                PacketKind::Request(_) | PacketKind::Ack => return,
+            }
+            let resources = &mut self.node.resources.inbound_resources;
+            let name = self.remote.identifier(packet.identifier);
+            if let Some(resource) = name.and_then(|name| resources.get_mut(name)) {
+                if let Ok(generation) = resource.deserialize(packet.data) {
+                    self.acks.push((packet.identifier, generation));
+                }
+            }
         }
     }
 }
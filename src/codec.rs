@@ -0,0 +1,195 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_diff::{Apply, SerdeDiff};
+
+#[cfg(feature = "bitpacked")]
+mod bitpacked;
+
+#[cfg(feature = "bitpacked")]
+pub use bitpacked::BitPacked;
+
+pub trait Codec: 'static + Send {
+    fn serialize_into<T: ?Sized + Serialize>(value: &T, buffer: &mut Vec<u8>) -> Option<()>;
+
+    fn serialize<T: ?Sized + Serialize>(value: &T) -> Option<Vec<u8>> {
+        let mut buffer = Vec::new();
+        Self::serialize_into(value, &mut buffer)?;
+        Some(buffer)
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Option<T>;
+
+    fn apply<T: SerdeDiff>(diff: &[u8], target: &mut T) -> Option<()>;
+}
+
+#[cfg(feature = "msgpack")]
+pub type DefaultCodec = MessagePack;
+
+#[cfg(all(not(feature = "msgpack"), feature = "bincode"))]
+pub type DefaultCodec = Bincode;
+
+#[cfg(all(
+    not(feature = "msgpack"),
+    not(feature = "bincode"),
+    feature = "postcard"
+))]
+pub type DefaultCodec = Postcard;
+
+#[cfg(not(any(feature = "msgpack", feature = "bincode", feature = "postcard")))]
+compile_error!("rayzo needs at least one of the `msgpack`, `bincode` or `postcard` features");
+
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn serialize_into<T: ?Sized + Serialize>(value: &T, buffer: &mut Vec<u8>) -> Option<()> {
+        use bincode::Options;
+        bincode::options().serialize_into(buffer, value).ok()
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+        use bincode::Options;
+        bincode::options().deserialize(data).ok()
+    }
+
+    fn apply<T: SerdeDiff>(diff: &[u8], target: &mut T) -> Option<()> {
+        let mut deserializer = bincode::Deserializer::from_slice(diff, bincode::options());
+        Apply::apply(&mut deserializer, target).ok()
+    }
+}
+
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn serialize_into<T: ?Sized + Serialize>(value: &T, buffer: &mut Vec<u8>) -> Option<()> {
+        rmp_serde::encode::write(buffer, value).ok()
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+        rmp_serde::from_slice(data).ok()
+    }
+
+    fn apply<T: SerdeDiff>(diff: &[u8], target: &mut T) -> Option<()> {
+        let mut deserializer = rmp_serde::Deserializer::new(diff);
+        Apply::apply(&mut deserializer, target).ok()
+    }
+}
+
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn serialize_into<T: ?Sized + Serialize>(value: &T, buffer: &mut Vec<u8>) -> Option<()> {
+        postcard::to_io(value, buffer).ok().map(|_| ())
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+        postcard::from_bytes(data).ok()
+    }
+
+    fn apply<T: SerdeDiff>(diff: &[u8], target: &mut T) -> Option<()> {
+        let mut deserializer = postcard::Deserializer::from_bytes(diff);
+        Apply::apply(&mut deserializer, target).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::FullOnly;
+    use crate::resources::Resources;
+    use crate::testing;
+    use crate::Reliability;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Circle(f32),
+        Rectangle { width: u16, height: u16 },
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Scene {
+        name: String,
+        shapes: Vec<Shape>,
+        tags: BTreeMap<u8, Option<i64>>,
+    }
+
+    fn scene() -> Scene {
+        let mut tags = BTreeMap::new();
+        tags.insert(1, Some(-40_000_000_000));
+        tags.insert(2, None);
+        Scene {
+            name: "scene".into(),
+            shapes: vec![
+                Shape::Circle(1.5),
+                Shape::Rectangle {
+                    width: 3,
+                    height: 600,
+                },
+            ],
+            tags,
+        }
+    }
+
+    fn round_trip<K: Codec>() {
+        let mut buffer = vec![7];
+        K::serialize_into(&scene(), &mut buffer).unwrap();
+        assert_eq!(buffer[0], 7);
+        assert_eq!(K::deserialize::<Scene>(&buffer[1..]), Some(scene()));
+        assert_eq!(K::serialize(&scene()).unwrap(), &buffer[1..]);
+        assert_eq!(K::deserialize::<Scene>(&buffer[1..buffer.len() / 2]), None);
+    }
+
+    // State, messages and the handshake all go through the codec of the peers.
+    fn synchronize<K: Codec>() {
+        let mut server = testing::server_with_codec::<K>();
+        server
+            .resources_mut()
+            .register_outbound_with("scene".into(), scene(), FullOnly);
+        server.register_message::<String>("chat".into(), Reliability::ReliableOrdered);
+        let mut client = testing::client(&mut server);
+        client
+            .resources_mut()
+            .register_inbound_with("scene".into(), scene(), FullOnly);
+        client.register_message::<String>("chat".into(), Reliability::ReliableOrdered);
+        testing::connect(&mut server, &mut client);
+
+        let outbound = server.resources_mut();
+        let outbound = outbound.outbound_mut::<Scene>("scene".into()).unwrap();
+        outbound.name = "changed".into();
+        client.send_message(String::from("hello")).unwrap();
+        testing::pump(&mut server, &mut [&mut client]);
+        let inbound = client.resources().inbound::<Scene>("scene".into()).unwrap();
+        assert_eq!(inbound.name, "changed");
+        assert_eq!(server.drain_messages::<String>().count(), 1);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_round_trips() {
+        round_trip::<Bincode>();
+        synchronize::<Bincode>();
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_round_trips() {
+        round_trip::<MessagePack>();
+        synchronize::<MessagePack>();
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn postcard_round_trips() {
+        round_trip::<Postcard>();
+        synchronize::<Postcard>();
+    }
+}
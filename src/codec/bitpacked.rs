@@ -0,0 +1,819 @@
+use super::Codec;
+use crate::quantize::bit_width;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::{ser, Serialize};
+use serde_diff::{Apply, SerdeDiff};
+use std::fmt::{self, Display, Formatter};
+
+// Packs values at bit granularity: bools and options take a single bit, integers are
+// varints and `Quantized` fields take exactly as many bits as their range requires.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BitPacked;
+
+impl Codec for BitPacked {
+    fn serialize_into<T: ?Sized + Serialize>(value: &T, buffer: &mut Vec<u8>) -> Option<()> {
+        value.serialize(&mut Serializer::new(buffer)).ok()
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+        T::deserialize(&mut Deserializer::new(data)).ok()
+    }
+
+    fn apply<T: SerdeDiff>(diff: &[u8], target: &mut T) -> Option<()> {
+        Apply::apply(&mut Deserializer::new(diff), target).ok()
+    }
+}
+
+#[derive(Debug)]
+struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+struct Serializer<'a> {
+    buffer: &'a mut Vec<u8>,
+    used: u8,
+    bits: Option<u8>,
+}
+
+impl<'a> Serializer<'a> {
+    fn new(buffer: &'a mut Vec<u8>) -> Self {
+        Self {
+            buffer,
+            used: 0,
+            bits: None,
+        }
+    }
+
+    fn write_bits(&mut self, mut value: u64, mut bits: u8) {
+        while bits > 0 {
+            if self.used == 0 {
+                self.buffer.push(0);
+            }
+            let count = (8 - self.used).min(bits);
+            if let Some(last) = self.buffer.last_mut() {
+                *last |= ((value & ((1 << count) - 1)) as u8) << self.used;
+            }
+            value >>= count;
+            bits -= count;
+            self.used = (self.used + count) % 8;
+        }
+    }
+
+    fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.write_bits(value & 0x7f | 0x80, 8);
+            value >>= 7;
+        }
+        self.write_bits(value, 8);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_varint(bytes.len() as u64);
+        if self.used == 0 {
+            self.buffer.extend_from_slice(bytes);
+        } else {
+            for byte in bytes {
+                self.write_bits(*byte as u64, 8);
+            }
+        }
+    }
+}
+
+struct Compound<'a, 'b> {
+    serializer: &'a mut Serializer<'b>,
+    delimited: bool,
+}
+
+impl<'a, 'b> Compound<'a, 'b> {
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        if self.delimited {
+            self.serializer.write_bits(1, 1);
+        }
+        value.serialize(&mut *self.serializer)
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.delimited {
+            self.serializer.write_bits(0, 1);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::Serializer for &'a mut Serializer<'b> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a, 'b>;
+    type SerializeTuple = Compound<'a, 'b>;
+    type SerializeTupleStruct = Compound<'a, 'b>;
+    type SerializeTupleVariant = Compound<'a, 'b>;
+    type SerializeMap = Compound<'a, 'b>;
+    type SerializeStruct = Compound<'a, 'b>;
+    type SerializeStructVariant = Compound<'a, 'b>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.write_bits(v as u64, 1);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.write_bits(v as u8 as u64, 8);
+        Ok(())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.write_varint(zigzag(v));
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.write_bits(v as u64, 8);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        match self.bits.take() {
+            Some(bits) => {
+                self.write_bits(v as u64, bits);
+                Ok(())
+            }
+            None => self.serialize_u64(v as u64),
+        }
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.write_varint(v);
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.write_bits(v.to_bits() as u64, 32);
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.write_bits(v.to_bits(), 64);
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.write_bytes(v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.write_bytes(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.write_bits(0, 1);
+        Ok(())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<()> {
+        self.write_bits(1, 1);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_u64(variant_index as u64)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.bits = bit_width(name);
+        value.serialize(&mut *self)?;
+        self.bits = None;
+        Ok(())
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.write_varint(variant_index as u64);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(Compound {
+            serializer: self,
+            delimited: true,
+        })
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Ok(Compound {
+            serializer: self,
+            delimited: false,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        self.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        self.write_varint(variant_index as u64);
+        self.serialize_tuple(len)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.serialize_seq(None)
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.serialize_tuple(len)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        self.write_varint(variant_index as u64);
+        self.serialize_tuple(len)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<'a, 'b> ser::SerializeSeq for Compound<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a, 'b> ser::SerializeTuple for Compound<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a, 'b> ser::SerializeTupleStruct for Compound<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a, 'b> ser::SerializeTupleVariant for Compound<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a, 'b> ser::SerializeMap for Compound<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        self.element(key)
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut *self.serializer)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a, 'b> ser::SerializeStruct for Compound<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a, 'b> ser::SerializeStructVariant for Compound<'a, 'b> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+struct Deserializer<'de> {
+    data: &'de [u8],
+    position: usize,
+    bits: Option<u8>,
+}
+
+impl<'de> Deserializer<'de> {
+    fn new(data: &'de [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            bits: None,
+        }
+    }
+
+    fn read_bits(&mut self, bits: u8) -> Result<u64> {
+        if self.position + bits as usize > self.data.len() * 8 {
+            return Err(Error("unexpected end of input".into()));
+        }
+        let mut value = 0;
+        let mut read = 0;
+        while read < bits {
+            let offset = (self.position % 8) as u8;
+            let count = (8 - offset).min(bits - read);
+            let chunk = (self.data[self.position / 8] >> offset) as u64 & ((1 << count) - 1);
+            value |= chunk << read;
+            read += count;
+            self.position += count as usize;
+        }
+        Ok(value)
+    }
+
+    fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_bits(8)?;
+            value |= (byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error("varint overflow".into()))
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_varint()? as usize;
+        if len > self.data.len() {
+            return Err(Error("unexpected end of input".into()));
+        }
+        (0..len)
+            .map(|_| self.read_bits(8).map(|byte| byte as u8))
+            .collect()
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        String::from_utf8(self.read_bytes()?).map_err(|_| Error("invalid utf-8".into()))
+    }
+}
+
+struct Access<'a, 'de> {
+    deserializer: &'a mut Deserializer<'de>,
+    remaining: Option<usize>,
+}
+
+impl<'a, 'de> Access<'a, 'de> {
+    fn has_next(&mut self) -> Result<bool> {
+        match &mut self.remaining {
+            Some(0) => Ok(false),
+            Some(remaining) => {
+                *remaining -= 1;
+                Ok(true)
+            }
+            None => self.deserializer.read_bool(),
+        }
+    }
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for Access<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.has_next()? {
+            seed.deserialize(&mut *self.deserializer).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.remaining
+    }
+}
+
+impl<'a, 'de> de::MapAccess<'de> for Access<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.has_next()? {
+            seed.deserialize(&mut *self.deserializer).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.deserializer)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index = self.read_varint()? as u32;
+        let value = seed.deserialize(index.into_deserializer())?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error("the bit-packed format is not self-describing".into()))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_bool(self.read_bool()?)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i8(self.read_bits(8)? as u8 as i8)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(unzigzag(self.read_varint()?))
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u8(self.read_bits(8)? as u8)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.bits.take() {
+            Some(bits) => visitor.visit_u32(self.read_bits(bits)? as u32),
+            None => self.deserialize_u64(visitor),
+        }
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(self.read_varint()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(f32::from_bits(self.read_bits(32)? as u32))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(f64::from_bits(self.read_bits(64)?))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let value = self.read_varint()?;
+        match std::char::from_u32(value as u32) {
+            Some(value) => visitor.visit_char(value),
+            None => Err(Error("invalid char".into())),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.read_string()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.read_string()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.read_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.read_bytes()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.read_bool()? {
+            visitor.visit_some(self)
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.bits = bit_width(name);
+        let value = visitor.visit_newtype_struct(&mut *self);
+        self.bits = None;
+        value
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Access {
+            deserializer: self,
+            remaining: None,
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Access {
+            deserializer: self,
+            remaining: Some(len),
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_map(Access {
+            deserializer: self,
+            remaining: None,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(self.read_varint()? as u32)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::{FloatRange, Quantized};
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    struct Unit;
+
+    impl FloatRange for Unit {
+        const MIN: f32 = -1.0;
+        const MAX: f32 = 1.0;
+        const PRECISION: f32 = 0.01;
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Action {
+        Idle,
+        Move {
+            x: Quantized<Unit>,
+            y: Quantized<Unit>,
+        },
+        Say(String),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Player {
+        alive: bool,
+        health: Option<u8>,
+        score: i64,
+        ratio: f64,
+        actions: Vec<Action>,
+        items: HashMap<String, u32>,
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let mut items = HashMap::new();
+        items.insert("sword".to_string(), 1);
+        let player = Player {
+            alive: true,
+            health: None,
+            score: -12_345_678_901,
+            ratio: 0.25,
+            actions: vec![
+                Action::Idle,
+                Action::Move {
+                    x: 0.5.into(),
+                    y: (-0.25).into(),
+                },
+                Action::Say("hi".into()),
+            ],
+            items,
+        };
+        let data = BitPacked::serialize(&player).unwrap();
+        assert_eq!(BitPacked::deserialize::<Player>(&data), Some(player));
+        assert_eq!(
+            BitPacked::deserialize::<Player>(&data[..data.len() - 1]),
+            None
+        );
+    }
+
+    #[test]
+    fn fields_take_only_the_bits_they_need() {
+        let value = (true, Quantized::<Unit>::new(0.5), Some(false));
+        let data = BitPacked::serialize(&value).unwrap();
+        // 1 + 8 + 2 bits.
+        assert_eq!(data.len(), 2);
+        assert_eq!(BitPacked::deserialize(&data), Some(value));
+    }
+
+    #[test]
+    fn zigzag_round_trips() {
+        for value in [0, 1, -1, i64::MAX, i64::MIN].iter().copied() {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+    }
+}
@@ -0,0 +1,478 @@
+use crate::codec::{Codec, DefaultCodec};
+use crate::diff::DiffStrategy;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+// Operations older than this are dropped, peers further behind get the full collection.
+const LOG_LIMIT: usize = 1024;
+
+// Collections that record their mutations so they can be synchronized as an operation log.
+// `version` counts every operation ever applied and the log holds the most recent ones.
+pub trait Operations {
+    type Operation: Serialize + DeserializeOwned;
+
+    fn version(&self) -> usize;
+
+    fn operations_since(&self, version: usize) -> Option<&[Self::Operation]>;
+
+    fn apply_operation(&mut self, operation: Self::Operation) -> Option<()>;
+}
+
+pub struct OperationLog<K = DefaultCodec> {
+    _phantom: PhantomData<fn() -> K>,
+}
+
+impl OperationLog {
+    pub fn new() -> Self {
+        Self::with_codec()
+    }
+}
+
+impl<K: Codec> OperationLog<K> {
+    pub fn with_codec() -> Self {
+        Self {
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Default for OperationLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Codec> Clone for OperationLog<K> {
+    fn clone(&self) -> Self {
+        Self::with_codec()
+    }
+}
+
+impl<T, K> DiffStrategy<T> for OperationLog<K>
+where
+    T: Operations,
+    K: Codec,
+{
+    fn is_changed(&self, old: &T, new: &T) -> bool {
+        new.version() != old.version()
+    }
+
+    fn diff(&mut self, old: &T, new: &T, buffer: &mut Vec<u8>) -> Option<bool> {
+        let operations = new.operations_since(old.version())?;
+        if operations.is_empty() {
+            return Some(false);
+        }
+        K::serialize_into(&(old.version(), operations), buffer)?;
+        Some(true)
+    }
+
+    fn apply(&mut self, diff: &[u8], target: &mut T) -> Option<()> {
+        let (version, operations) = K::deserialize::<(usize, Vec<T::Operation>)>(diff)?;
+        if version != target.version() {
+            return None;
+        }
+        for operation in operations {
+            target.apply_operation(operation)?;
+        }
+        Some(())
+    }
+}
+
+fn operations_since<O>(log: &[O], current: usize, version: usize) -> Option<&[O]> {
+    let skip = version.checked_sub(current - log.len())?;
+    log.get(skip..)
+}
+
+fn record<O>(log: &mut Vec<O>, version: &mut usize, operation: O) {
+    if log.len() == LOG_LIMIT {
+        log.drain(..LOG_LIMIT / 2);
+    }
+    log.push(operation);
+    *version += 1;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum VecOperation<T> {
+    Push(T),
+    Pop,
+    Insert(usize, T),
+    Remove(usize),
+    Set(usize, T),
+    Move(usize, usize),
+    Clear,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncVec<T> {
+    version: usize,
+    items: Vec<T>,
+    #[serde(skip, default = "Vec::new")]
+    log: Vec<VecOperation<T>>,
+}
+
+impl<T: Clone> SyncVec<T> {
+    pub fn new() -> Self {
+        Self::from(Vec::new())
+    }
+
+    pub fn push(&mut self, item: T) {
+        self.record(VecOperation::Push(item.clone()));
+        self.items.push(item);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let item = self.items.pop()?;
+        self.record(VecOperation::Pop);
+        Some(item)
+    }
+
+    pub fn insert(&mut self, index: usize, item: T) {
+        self.items.insert(index, item.clone());
+        self.record(VecOperation::Insert(index, item));
+    }
+
+    pub fn remove(&mut self, index: usize) -> T {
+        let item = self.items.remove(index);
+        self.record(VecOperation::Remove(index));
+        item
+    }
+
+    pub fn set(&mut self, index: usize, item: T) {
+        self.items[index] = item.clone();
+        self.record(VecOperation::Set(index, item));
+    }
+
+    pub fn update<F: FnOnce(&mut T)>(&mut self, index: usize, update: F) {
+        update(&mut self.items[index]);
+        self.record(VecOperation::Set(index, self.items[index].clone()));
+    }
+
+    pub fn move_item(&mut self, from: usize, to: usize) {
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+        self.record(VecOperation::Move(from, to));
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.record(VecOperation::Clear);
+    }
+
+    fn record(&mut self, operation: VecOperation<T>) {
+        record(&mut self.log, &mut self.version, operation);
+    }
+}
+
+impl<T: Clone> Default for SyncVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<Vec<T>> for SyncVec<T> {
+    fn from(items: Vec<T>) -> Self {
+        Self {
+            version: 0,
+            items,
+            log: Vec::new(),
+        }
+    }
+}
+
+// Clones are used as snapshots, which only need the version, so the log isn't copied.
+impl<T: Clone> Clone for SyncVec<T> {
+    fn clone(&self) -> Self {
+        Self {
+            version: self.version,
+            items: self.items.clone(),
+            log: Vec::new(),
+        }
+    }
+}
+
+impl<T> Deref for SyncVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+impl<T> Operations for SyncVec<T>
+where
+    T: Clone + Serialize + DeserializeOwned,
+{
+    type Operation = VecOperation<T>;
+
+    fn version(&self) -> usize {
+        self.version
+    }
+
+    fn operations_since(&self, version: usize) -> Option<&[Self::Operation]> {
+        operations_since(&self.log, self.version, version)
+    }
+
+    fn apply_operation(&mut self, operation: Self::Operation) -> Option<()> {
+        let len = self.items.len();
+        match operation {
+            VecOperation::Push(item) => self.items.push(item),
+            VecOperation::Pop => {
+                self.items.pop()?;
+            }
+            VecOperation::Insert(index, item) if index <= len => self.items.insert(index, item),
+            VecOperation::Remove(index) if index < len => {
+                self.items.remove(index);
+            }
+            VecOperation::Set(index, item) if index < len => self.items[index] = item,
+            VecOperation::Move(from, to) if from < len && to < len => {
+                let item = self.items.remove(from);
+                self.items.insert(to, item);
+            }
+            VecOperation::Clear => self.items.clear(),
+            _ => return None,
+        }
+        self.version += 1;
+        Some(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MapOperation<K, V> {
+    Insert(K, V),
+    Remove(K),
+    Clear,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncMap<K, V>
+where
+    K: Eq + Hash,
+{
+    version: usize,
+    items: HashMap<K, V>,
+    #[serde(skip, default = "Vec::new")]
+    log: Vec<MapOperation<K, V>>,
+}
+
+impl<K, V> SyncMap<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self::from(HashMap::new())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.record(MapOperation::Insert(key.clone(), value.clone()));
+        self.items.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.items.remove(key)?;
+        self.record(MapOperation::Remove(key.clone()));
+        Some(value)
+    }
+
+    pub fn update<F: FnOnce(&mut V)>(&mut self, key: &K, update: F) -> bool {
+        match self.items.get_mut(key) {
+            Some(value) => {
+                update(value);
+                let operation = MapOperation::Insert(key.clone(), value.clone());
+                self.record(operation);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.record(MapOperation::Clear);
+    }
+
+    fn record(&mut self, operation: MapOperation<K, V>) {
+        record(&mut self.log, &mut self.version, operation);
+    }
+}
+
+impl<K, V> Default for SyncMap<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> From<HashMap<K, V>> for SyncMap<K, V>
+where
+    K: Eq + Hash,
+{
+    fn from(items: HashMap<K, V>) -> Self {
+        Self {
+            version: 0,
+            items,
+            log: Vec::new(),
+        }
+    }
+}
+
+impl<K, V> Clone for SyncMap<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            version: self.version,
+            items: self.items.clone(),
+            log: Vec::new(),
+        }
+    }
+}
+
+impl<K, V> Deref for SyncMap<K, V>
+where
+    K: Eq + Hash,
+{
+    type Target = HashMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+impl<K, V> Operations for SyncMap<K, V>
+where
+    K: Eq + Hash + Clone + Serialize + DeserializeOwned,
+    V: Clone + Serialize + DeserializeOwned,
+{
+    type Operation = MapOperation<K, V>;
+
+    fn version(&self) -> usize {
+        self.version
+    }
+
+    fn operations_since(&self, version: usize) -> Option<&[Self::Operation]> {
+        operations_since(&self.log, self.version, version)
+    }
+
+    fn apply_operation(&mut self, operation: Self::Operation) -> Option<()> {
+        match operation {
+            MapOperation::Insert(key, value) => {
+                self.items.insert(key, value);
+            }
+            MapOperation::Remove(key) => {
+                self.items.remove(&key)?;
+            }
+            MapOperation::Clear => self.items.clear(),
+        }
+        self.version += 1;
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::Resources;
+    use crate::testing;
+
+    fn replay<T: Operations + Clone>(old: &T, new: &T) -> Option<T> {
+        let mut strategy = OperationLog::new();
+        let mut buffer = Vec::new();
+        assert!(strategy.diff(old, new, &mut buffer)?);
+        let mut target = old.clone();
+        strategy.apply(&buffer, &mut target)?;
+        Some(target)
+    }
+
+    #[test]
+    fn vec_operations_are_replayed() {
+        let mut items = SyncVec::from(vec![1, 2, 3]);
+        let old = items.clone();
+        items.push(4);
+        items.insert(0, 0);
+        items.remove(2);
+        items.set(1, 10);
+        items.update(2, |item| *item *= 10);
+        items.move_item(0, 3);
+        items.pop();
+        assert_eq!(&*items, &[10, 30, 4]);
+        let replayed = replay(&old, &items).unwrap();
+        assert_eq!(&*replayed, &*items);
+        assert_eq!(replayed.version(), items.version());
+    }
+
+    #[test]
+    fn map_operations_are_replayed() {
+        let key = |key: &str| key.to_string();
+        let mut items = SyncMap::new();
+        items.insert(key("a"), 1);
+        let old = items.clone();
+        items.insert(key("b"), 2);
+        items.update(&key("a"), |value| *value += 10);
+        items.remove(&key("b"));
+        let replayed = replay(&old, &items).unwrap();
+        assert_eq!(&*replayed, &*items);
+        assert_eq!(replayed.get("a"), Some(&11));
+    }
+
+    #[test]
+    fn trimmed_logs_fall_back_to_the_full_state() {
+        let mut items = SyncVec::new();
+        let old = items.clone();
+        for i in 0..LOG_LIMIT + 1 {
+            items.push(i);
+        }
+        assert!(items.operations_since(old.version()).is_none());
+        assert!(replay(&old, &items).is_none());
+        let recent = items.version() - 10;
+        assert_eq!(items.operations_since(recent).map(|o| o.len()), Some(10));
+    }
+
+    #[test]
+    fn operations_for_another_version_are_rejected() {
+        let mut strategy = OperationLog::new();
+        let mut items = SyncVec::from(vec![1]);
+        let old = items.clone();
+        items.push(2);
+        let mut buffer = Vec::new();
+        strategy.diff(&old, &items, &mut buffer).unwrap();
+        assert_eq!(strategy.apply(&buffer, &mut items), None);
+        assert_eq!(items.apply_operation(VecOperation::Remove(5)), None);
+        assert_eq!(items.apply_operation(VecOperation::Move(0, 2)), None);
+        assert_eq!(&*items, &[1, 2]);
+    }
+
+    #[test]
+    fn collections_are_synchronized() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        let list = SyncVec::<u32>::new();
+        resources.register_outbound_with("list".into(), list, OperationLog::new());
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        let list = SyncVec::<u32>::new();
+        resources.register_inbound_with("list".into(), list, OperationLog::new());
+        testing::connect(&mut server, &mut client);
+
+        for round in 0..3 {
+            let resources = server.resources_mut();
+            let list = resources.outbound_mut::<SyncVec<u32>>("list".into());
+            let list = &mut **list.unwrap();
+            list.push(round);
+            list.push(round + 10);
+            list.remove(0);
+            testing::pump(&mut server, &mut [&mut client]);
+            let received = client.resources().inbound::<SyncVec<u32>>("list".into());
+            let expected = server.resources().outbound::<SyncVec<u32>>("list".into());
+            assert_eq!(&***received.unwrap(), &***expected.unwrap());
+        }
+    }
+}
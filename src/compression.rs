@@ -0,0 +1,294 @@
+// Without a compression feature `Compression` cannot be built and everything here is a no-op.
+#![cfg_attr(
+    not(any(feature = "zstd", feature = "lz4")),
+    allow(dead_code, unused_variables)
+)]
+
+use crate::wire::{read_varint, write_varint};
+use crate::ResourcePacket;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// Guards against packets claiming an absurd decompressed size.
+const MAX_DECOMPRESSED_SIZE: usize = 1 << 24;
+
+const DEFAULT_THRESHOLD: usize = 64;
+
+enum Algorithm {
+    #[cfg(feature = "zstd")]
+    Zstd(
+        zstd::bulk::Compressor<'static>,
+        zstd::bulk::Decompressor<'static>,
+    ),
+    #[cfg(feature = "lz4")]
+    Lz4(Vec<u8>),
+}
+
+// Packet bodies smaller than the threshold are sent as is. Both ends of a connection must be
+// configured with the same dictionary.
+pub struct Compression {
+    algorithm: Algorithm,
+    threshold: usize,
+}
+
+impl Compression {
+    #[cfg(feature = "zstd")]
+    pub fn zstd(level: i32, dictionary: &[u8]) -> std::io::Result<Self> {
+        Ok(Self {
+            algorithm: Algorithm::Zstd(
+                zstd::bulk::Compressor::with_dictionary(level, dictionary)?,
+                zstd::bulk::Decompressor::with_dictionary(dictionary)?,
+            ),
+            threshold: DEFAULT_THRESHOLD,
+        })
+    }
+
+    #[cfg(feature = "lz4")]
+    pub fn lz4(dictionary: Vec<u8>) -> Self {
+        Self {
+            algorithm: Algorithm::Lz4(dictionary),
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold;
+    }
+
+    fn id(&self) -> u8 {
+        match self.algorithm {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(..) => 1,
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4(..) => 2,
+        }
+    }
+
+    fn bound(&self, len: usize) -> usize {
+        match self.algorithm {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(..) => zstd::zstd_safe::compress_bound(len),
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4(..) => lz4_flex::block::get_maximum_output_size(len),
+        }
+    }
+
+    fn compress_into(&mut self, data: &[u8], output: &mut [u8]) -> Option<usize> {
+        match self.algorithm {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(ref mut compressor, _) => {
+                compressor.compress_to_buffer(data, output).ok()
+            }
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4(ref dictionary) => {
+                lz4_flex::block::compress_into_with_dict(data, output, dictionary).ok()
+            }
+        }
+    }
+
+    fn decompress_into(&mut self, data: &[u8], output: &mut [u8]) -> Option<usize> {
+        match self.algorithm {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(_, ref mut decompressor) => {
+                decompressor.decompress_to_buffer(data, output).ok()
+            }
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4(ref dictionary) => {
+                lz4_flex::block::decompress_into_with_dict(data, output, dictionary).ok()
+            }
+        }
+    }
+}
+
+// The compression id goes in the upper half of the packet tag, followed by the uncompressed
+// body length. Headers stay readable so only the body is compressed.
+pub(crate) struct Compressions<C> {
+    pub(crate) default: Option<Compression>,
+    pub(crate) connections: HashMap<C, Compression>,
+    buffer: Vec<u8>,
+}
+
+impl<C> Default for Compressions<C> {
+    fn default() -> Self {
+        Self {
+            default: None,
+            connections: HashMap::new(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl<C> Compressions<C>
+where
+    C: Eq + Hash,
+{
+    fn get_mut(&mut self, connection: &C) -> Option<&mut Compression> {
+        match self.connections.get_mut(connection) {
+            Some(compression) => Some(compression),
+            None => self.default.as_mut(),
+        }
+    }
+
+    pub(crate) fn compress<'a>(&'a mut self, connection: &C, packet: &'a [u8]) -> &'a [u8] {
+        let header = match ResourcePacket::read(packet) {
+            Some(read) => packet.len() - read.data.len(),
+            None => return packet,
+        };
+        let body = &packet[header..];
+        let compression = match self.connections.get_mut(connection) {
+            Some(compression) => compression,
+            None => match self.default.as_mut() {
+                Some(compression) => compression,
+                None => return packet,
+            },
+        };
+        if body.len() < compression.threshold {
+            return packet;
+        }
+        let buffer = &mut self.buffer;
+        buffer.clear();
+        buffer.extend_from_slice(&packet[..header]);
+        buffer[0] |= compression.id() << 4;
+        write_varint(body.len() as u32, buffer);
+        let start = buffer.len();
+        buffer.resize(start + compression.bound(body.len()), 0);
+        match compression.compress_into(body, &mut buffer[start..]) {
+            Some(written) if start + written < packet.len() => {
+                buffer.truncate(start + written);
+                buffer
+            }
+            _ => packet,
+        }
+    }
+
+    pub(crate) fn decompress(&mut self, connection: &C, data: Vec<u8>) -> Option<Vec<u8>> {
+        let read = match ResourcePacket::read(&data) {
+            Some(read) if read.compression != 0 => read,
+            _ => return Some(data),
+        };
+        let compression = self.get_mut(connection)?;
+        if compression.id() != read.compression {
+            return None;
+        }
+        let header = data.len() - read.data.len();
+        let mut body = read.data;
+        let len = read_varint(&mut body)? as usize;
+        if len > MAX_DECOMPRESSED_SIZE {
+            return None;
+        }
+        let mut decompressed = Vec::with_capacity(header + len);
+        decompressed.extend_from_slice(&data[..header]);
+        decompressed[0] &= 0x0f;
+        decompressed.resize(header + len, 0);
+        match compression.decompress_into(body, &mut decompressed[header..]) {
+            Some(written) if written == len => Some(decompressed),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "lz4"))]
+mod tests {
+    use super::*;
+    use crate::transport::ConnectionId;
+    use crate::{testing, PacketKind, Reliability};
+
+    fn dictionary() -> Vec<u8> {
+        b"position velocity rotation ".repeat(4)
+    }
+
+    fn packet(body: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        ResourcePacket::write_header(PacketKind::Message, 300, &mut packet);
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    fn compressions() -> Compressions<u8> {
+        Compressions {
+            default: Some(Compression::lz4(dictionary())),
+            ..Compressions::default()
+        }
+    }
+
+    #[test]
+    fn bodies_round_trip_with_a_readable_header() {
+        let mut compressions = compressions();
+        let original = packet(&b"position velocity ".repeat(16));
+        let compressed = compressions.compress(&0, &original).to_vec();
+        assert!(compressed.len() < original.len());
+        let read = ResourcePacket::read(&compressed).unwrap();
+        assert_eq!(read.kind, PacketKind::Message);
+        assert_eq!(read.identifier, 300);
+        assert_eq!(read.compression, 2);
+        assert_eq!(compressions.decompress(&0, compressed), Some(original));
+    }
+
+    #[test]
+    fn small_or_incompressible_bodies_are_sent_as_is() {
+        let mut compressions = compressions();
+        let small = packet(b"position");
+        assert_eq!(compressions.compress(&0, &small), &small[..]);
+        let noise: Vec<u8> = (0..200u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let noise = packet(&noise);
+        assert_eq!(compressions.compress(&0, &noise), &noise[..]);
+        assert_eq!(compressions.decompress(&0, noise.clone()), Some(noise));
+    }
+
+    #[test]
+    fn connections_override_the_default() {
+        let mut compressions = compressions();
+        compressions.default = None;
+        compressions
+            .connections
+            .insert(1, Compression::lz4(dictionary()));
+        let original = packet(&b"rotation ".repeat(16));
+        assert_eq!(compressions.compress(&0, &original), &original[..]);
+        let compressed = compressions.compress(&1, &original).to_vec();
+        assert_ne!(compressed, original);
+        assert_eq!(compressions.decompress(&0, compressed.clone()), None);
+        assert_eq!(compressions.decompress(&1, compressed), Some(original));
+    }
+
+    #[test]
+    fn corrupt_packets_are_dropped() {
+        let mut compressions = compressions();
+        let original = packet(&b"velocity ".repeat(16));
+        let compressed = compressions.compress(&0, &original).to_vec();
+
+        let mut truncated = compressed.clone();
+        truncated.truncate(compressed.len() - 2);
+        assert_eq!(compressions.decompress(&0, truncated), None);
+
+        let mut huge = packet(&[]);
+        huge[0] |= 2 << 4;
+        write_varint(MAX_DECOMPRESSED_SIZE as u32 + 1, &mut huge);
+        huge.extend_from_slice(&compressed[compressed.len() - 8..]);
+        assert_eq!(compressions.decompress(&0, huge), None);
+
+        let mut unknown = compressed;
+        unknown[0] = (unknown[0] & 0x0f) | 1 << 4;
+        assert_eq!(compressions.decompress(&0, unknown), None);
+    }
+
+    #[test]
+    fn compressed_messages_are_delivered() {
+        let mut server = testing::server();
+        server.register_message::<String>("chat".into(), Reliability::ReliableOrdered);
+        server.set_compression(Some(Compression::lz4(dictionary())));
+        let mut client = testing::client(&mut server);
+        client.register_message::<String>("chat".into(), Reliability::ReliableOrdered);
+        client.set_compression(Some(Compression::lz4(dictionary())));
+        testing::connect(&mut server, &mut client);
+
+        let text = "position velocity rotation ".repeat(10);
+        client.send_message(text.clone()).unwrap();
+        testing::pump(&mut server, &mut [&mut client]);
+        let received: Vec<_> = server.drain_messages::<String>().collect();
+        assert_eq!(received, vec![(ConnectionId(0), text)]);
+    }
+}
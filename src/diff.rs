@@ -0,0 +1,341 @@
+use crate::codec::{Codec, DefaultCodec};
+use crate::wire::{read_varint, write_varint};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_diff::{Diff, SerdeDiff};
+use std::marker::PhantomData;
+
+const MAX_LEN: usize = 1 << 24;
+
+// `diff` returns `Some(false)` when nothing changed and `None` when the change can't be
+// expressed as a diff, in which case the full state is sent instead. `is_changed` lets
+// strategies that track writes skip resources that were borrowed mutably but left untouched.
+pub trait DiffStrategy<T>: 'static + Send {
+    fn is_changed(&self, _old: &T, _new: &T) -> bool {
+        true
+    }
+
+    fn diff(&mut self, old: &T, new: &T, buffer: &mut Vec<u8>) -> Option<bool>;
+
+    fn apply(&mut self, diff: &[u8], target: &mut T) -> Option<()>;
+}
+
+impl<T: 'static> DiffStrategy<T> for Box<dyn DiffStrategy<T>> {
+    fn is_changed(&self, old: &T, new: &T) -> bool {
+        (**self).is_changed(old, new)
+    }
+
+    fn diff(&mut self, old: &T, new: &T, buffer: &mut Vec<u8>) -> Option<bool> {
+        (**self).diff(old, new, buffer)
+    }
+
+    fn apply(&mut self, diff: &[u8], target: &mut T) -> Option<()> {
+        (**self).apply(diff, target)
+    }
+}
+
+pub struct SerdeDiffStrategy<K = DefaultCodec> {
+    _phantom: PhantomData<fn() -> K>,
+}
+
+impl SerdeDiffStrategy {
+    pub fn new() -> Self {
+        Self::with_codec()
+    }
+}
+
+impl<K: Codec> SerdeDiffStrategy<K> {
+    pub fn with_codec() -> Self {
+        Self {
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Default for SerdeDiffStrategy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Codec> Clone for SerdeDiffStrategy<K> {
+    fn clone(&self) -> Self {
+        Self::with_codec()
+    }
+}
+
+impl<T, K> DiffStrategy<T> for SerdeDiffStrategy<K>
+where
+    T: SerdeDiff,
+    K: Codec,
+{
+    fn diff(&mut self, old: &T, new: &T, buffer: &mut Vec<u8>) -> Option<bool> {
+        let diff = Diff::serializable(old, new);
+        K::serialize_into(&diff, buffer)?;
+        Some(diff.has_changes())
+    }
+
+    fn apply(&mut self, diff: &[u8], target: &mut T) -> Option<()> {
+        K::apply(diff, target)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FullOnly;
+
+impl<T> DiffStrategy<T> for FullOnly {
+    fn diff(&mut self, _old: &T, _new: &T, _buffer: &mut Vec<u8>) -> Option<bool> {
+        None
+    }
+
+    fn apply(&mut self, _diff: &[u8], _target: &mut T) -> Option<()> {
+        None
+    }
+}
+
+// User supplied delta: `diff` returns `None` when nothing changed.
+pub struct Delta<D, F, A, K = DefaultCodec> {
+    diff: F,
+    apply: A,
+    _phantom: PhantomData<fn() -> (D, K)>,
+}
+
+impl<D, F, A> Delta<D, F, A> {
+    pub fn new(diff: F, apply: A) -> Self {
+        Self::with_codec(diff, apply)
+    }
+}
+
+impl<D, F, A, K> Delta<D, F, A, K> {
+    pub fn with_codec(diff: F, apply: A) -> Self {
+        Self {
+            diff,
+            apply,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<D, F: Clone, A: Clone, K> Clone for Delta<D, F, A, K> {
+    fn clone(&self) -> Self {
+        Self::with_codec(self.diff.clone(), self.apply.clone())
+    }
+}
+
+impl<T, D, F, A, K> DiffStrategy<T> for Delta<D, F, A, K>
+where
+    T: 'static,
+    D: 'static + Serialize + DeserializeOwned,
+    F: 'static + Fn(&T, &T) -> Option<D> + Send,
+    A: 'static + Fn(&mut T, D) + Send,
+    K: Codec,
+{
+    fn diff(&mut self, old: &T, new: &T, buffer: &mut Vec<u8>) -> Option<bool> {
+        match (self.diff)(old, new) {
+            Some(delta) => K::serialize_into(&delta, buffer).map(|_| true),
+            None => Some(false),
+        }
+    }
+
+    fn apply(&mut self, diff: &[u8], target: &mut T) -> Option<()> {
+        (self.apply)(target, K::deserialize(diff)?);
+        Some(())
+    }
+}
+
+// XORs the serialized new state against the serialized baseline and run-length encodes the
+// result as alternating runs of unchanged and changed bytes. Works for any serializable type as
+// long as its serialization is deterministic, which rules out `HashMap` fields.
+pub struct XorDelta<K = DefaultCodec> {
+    old: Vec<u8>,
+    new: Vec<u8>,
+    _phantom: PhantomData<fn() -> K>,
+}
+
+impl XorDelta {
+    pub fn new() -> Self {
+        Self::with_codec()
+    }
+}
+
+impl<K: Codec> XorDelta<K> {
+    pub fn with_codec() -> Self {
+        Self {
+            old: Vec::new(),
+            new: Vec::new(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Default for XorDelta {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The buffers are scratch space, so clones start empty.
+impl<K: Codec> Clone for XorDelta<K> {
+    fn clone(&self) -> Self {
+        Self::with_codec()
+    }
+}
+
+impl<T, K> DiffStrategy<T> for XorDelta<K>
+where
+    T: Serialize + DeserializeOwned,
+    K: Codec,
+{
+    fn diff(&mut self, old: &T, new: &T, buffer: &mut Vec<u8>) -> Option<bool> {
+        self.old.clear();
+        K::serialize_into(old, &mut self.old)?;
+        self.new.clear();
+        K::serialize_into(new, &mut self.new)?;
+        if self.old == self.new {
+            return Some(false);
+        }
+        write_xor(&self.old, &self.new, buffer);
+        Some(true)
+    }
+
+    fn apply(&mut self, diff: &[u8], target: &mut T) -> Option<()> {
+        self.old.clear();
+        K::serialize_into(target, &mut self.old)?;
+        apply_xor(diff, &mut self.old)?;
+        *target = K::deserialize(&self.old)?;
+        Some(())
+    }
+}
+
+// Writes the length of `new`, then alternating runs of unchanged and changed bytes, the changed
+// ones XORed with `old`. Bytes past the end of `old` are XORed with zero.
+pub(crate) fn write_xor(old: &[u8], new: &[u8], buffer: &mut Vec<u8>) {
+    let xor = |i: usize| new[i] ^ old.get(i).copied().unwrap_or(0);
+    write_varint(new.len() as u32, buffer);
+    let mut i = 0;
+    while i < new.len() {
+        let start = i;
+        while i < new.len() && xor(i) == 0 {
+            i += 1;
+        }
+        write_varint((i - start) as u32, buffer);
+        let start = i;
+        while i < new.len() && xor(i) != 0 {
+            i += 1;
+        }
+        write_varint((i - start) as u32, buffer);
+        buffer.extend((start..i).map(xor));
+    }
+}
+
+// Turns `target` from the old encoding into the new one, in place.
+pub(crate) fn apply_xor(mut diff: &[u8], target: &mut Vec<u8>) -> Option<()> {
+    let len = read_varint(&mut diff)? as usize;
+    if len > MAX_LEN {
+        return None;
+    }
+    target.resize(len, 0);
+    let mut i = 0;
+    while !diff.is_empty() {
+        i += read_varint(&mut diff)? as usize;
+        let changed = read_varint(&mut diff)? as usize;
+        if changed > diff.len() || i + changed > len {
+            return None;
+        }
+        for (byte, xor) in target[i..i + changed].iter_mut().zip(&diff[..changed]) {
+            *byte ^= xor;
+        }
+        diff = &diff[changed..];
+        i += changed;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::Resources;
+    use crate::testing;
+
+    fn round_trip<T, D>(strategy: &mut D, old: &T, new: &T) -> (Option<bool>, T)
+    where
+        T: Clone,
+        D: DiffStrategy<T>,
+    {
+        let mut buffer = Vec::new();
+        let changed = strategy.diff(old, new, &mut buffer);
+        let mut target = old.clone();
+        if changed == Some(true) {
+            strategy.apply(&buffer, &mut target).unwrap();
+        }
+        (changed, target)
+    }
+
+    #[test]
+    fn xor_delta_handles_growing_and_shrinking_states() {
+        let mut strategy = XorDelta::new();
+        let old = vec![1u32, 2, 3, 4, 5, 6, 7, 8];
+        let mut new = old.clone();
+        new[6] = 70;
+        assert_eq!(round_trip(&mut strategy, &old, &new), (Some(true), new));
+        let longer = [&old[..], &[9, 10]].concat();
+        assert_eq!(
+            round_trip(&mut strategy, &old, &longer),
+            (Some(true), longer)
+        );
+        let shorter = old[..3].to_vec();
+        assert_eq!(
+            round_trip(&mut strategy, &old, &shorter),
+            (Some(true), shorter)
+        );
+        assert_eq!(round_trip(&mut strategy, &old, &old), (Some(false), old));
+    }
+
+    #[test]
+    fn xor_delta_rejects_runs_past_the_end() {
+        let mut strategy = XorDelta::new();
+        let mut target = vec![1u8, 2, 3];
+        let mut diff = Vec::new();
+        for value in [4, 0, 8].iter().copied() {
+            write_varint(value, &mut diff);
+        }
+        diff.extend_from_slice(&[1; 8]);
+        assert_eq!(strategy.apply(&diff, &mut target), None);
+        let mut diff = Vec::new();
+        write_varint(MAX_LEN as u32 + 1, &mut diff);
+        assert_eq!(strategy.apply(&diff, &mut target), None);
+        assert_eq!(target, vec![1, 2, 3]);
+    }
+
+    fn counter() -> impl DiffStrategy<i64> + Clone {
+        let diff = |old: &i64, new: &i64| Some(new - old).filter(|delta| *delta != 0);
+        Delta::new(diff, |target: &mut i64, delta: i64| *target += delta)
+    }
+
+    #[test]
+    fn delta_uses_the_user_functions() {
+        assert_eq!(round_trip(&mut counter(), &5, &12), (Some(true), 12));
+        assert_eq!(round_trip(&mut counter(), &5, &5), (Some(false), 5));
+        assert_eq!(round_trip(&mut FullOnly, &5, &12), (None, 5));
+    }
+
+    #[test]
+    fn resources_synchronize_with_custom_strategies() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        resources.register_outbound_with("counter".into(), 0i64, counter());
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_inbound_with("counter".into(), 0i64, counter());
+        testing::connect(&mut server, &mut client);
+
+        for value in [3, 10, -4].iter() {
+            **server
+                .resources_mut()
+                .outbound_mut::<i64>("counter".into())
+                .unwrap() = *value;
+            testing::pump(&mut server, &mut [&mut client]);
+            let counter = client.resources().inbound::<i64>("counter".into()).unwrap();
+            assert_eq!(**counter, *value);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    entries: BTreeMap<String, String>,
+    ids: Ids,
+}
+
+impl Schema {
+    pub fn declare<T: ?Sized>(&mut self, identifier: &str) {
+        self.entries
+            .insert(identifier.into(), type_name_without_path::<T>());
+        self.ids.insert(identifier);
+    }
+
+    pub fn type_name(&self, identifier: &str) -> Option<&str> {
+        self.entries.get(identifier).map(|s| s.as_str())
+    }
+
+    pub fn id(&self, identifier: &str) -> Option<u32> {
+        self.ids.id(identifier)
+    }
+
+    pub fn identifier(&self, id: u32) -> Option<&str> {
+        self.ids.identifier(id)
+    }
+
+    pub(crate) fn ids(&self) -> &Ids {
+        &self.ids
+    }
+
+    pub fn hash(&self) -> u64 {
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        for (identifier, type_name) in self.entries.iter() {
+            for byte in identifier
+                .bytes()
+                .chain(Some(0))
+                .chain(type_name.bytes())
+                .chain(Some(0))
+            {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+}
+
+// Ids are handed out in declaration order and never change, so packets already on the wire keep
+// their meaning. Peers may declare in another order, which is why the client uses the server's.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Ids {
+    identifiers: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl Ids {
+    pub(crate) fn insert(&mut self, identifier: &str) -> u32 {
+        if let Some(id) = self.ids.get(identifier) {
+            return *id;
+        }
+        let id = self.identifiers.len() as u32;
+        self.identifiers.push(identifier.into());
+        self.ids.insert(identifier.into(), id);
+        id
+    }
+
+    pub(crate) fn id(&self, identifier: &str) -> Option<u32> {
+        self.ids.get(identifier).copied()
+    }
+
+    pub(crate) fn identifier(&self, id: u32) -> Option<&str> {
+        self.identifiers.get(id as usize).map(|s| s.as_str())
+    }
+
+    pub(crate) fn identifiers(&self) -> &[String] {
+        &self.identifiers
+    }
+}
+
+impl From<Vec<String>> for Ids {
+    fn from(identifiers: Vec<String>) -> Self {
+        let mut ids = Self::default();
+        for identifier in identifiers.iter() {
+            ids.insert(identifier);
+        }
+        ids
+    }
+}
+
+// Client and server are usually separate crates, so module paths are left out of the schema.
+pub(crate) fn type_name_without_path<T: ?Sized>() -> String {
+    let mut name = String::new();
+    let mut segment = String::new();
+    let mut chars = std::any::type_name::<T>().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            segment.clear();
+        } else if c.is_alphanumeric() || c == '_' {
+            segment.push(c);
+        } else {
+            name.push_str(&segment);
+            segment.clear();
+            name.push(c);
+        }
+    }
+    name.push_str(&segment);
+    name
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Hello {
+    pub(crate) protocol_version: u32,
+    pub(crate) application_id: String,
+    pub(crate) schema_hash: u64,
+}
+
+impl Hello {
+    pub(crate) fn new(application_id: &str, schema: &Schema) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            application_id: application_id.into(),
+            schema_hash: schema.hash(),
+        }
+    }
+
+    pub(crate) fn verify(
+        &self,
+        application_id: &str,
+        schema: &Schema,
+    ) -> Result<(), HandshakeError> {
+        if self.protocol_version != PROTOCOL_VERSION {
+            Err(HandshakeError::ProtocolVersion {
+                expected: PROTOCOL_VERSION,
+                found: self.protocol_version,
+            })
+        } else if self.application_id != application_id {
+            Err(HandshakeError::ApplicationId {
+                expected: application_id.into(),
+                found: self.application_id.clone(),
+            })
+        } else if self.schema_hash != schema.hash() {
+            Err(HandshakeError::Schema {
+                expected: schema.hash(),
+                found: self.schema_hash,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum HandshakeResponse {
+    Accepted(Vec<String>),
+    Rejected(HandshakeError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HandshakeError {
+    ProtocolVersion { expected: u32, found: u32 },
+    ApplicationId { expected: String, found: String },
+    Schema { expected: u64, found: u64 },
+}
+
+impl Display for HandshakeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::ProtocolVersion { expected, found } => write!(
+                f,
+                "protocol version mismatch: expected {}, found {}",
+                expected, found
+            ),
+            HandshakeError::ApplicationId { expected, found } => write!(
+                f,
+                "application id mismatch: expected {:?}, found {:?}",
+                expected, found
+            ),
+            HandshakeError::Schema { expected, found } => write!(
+                f,
+                "resource schema mismatch: expected {:016x}, found {:016x}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected,
+    Rejected(HandshakeError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::FullOnly;
+    use crate::resources::Resources;
+    use crate::server::{InboundIdentifier, ServerEvent};
+    use crate::testing::{self, TestClient, TestServer};
+    use crate::transport::ConnectionId;
+    use crate::{PacketKind, Reliability, ResourcePacket, Target};
+
+    fn position() -> InboundIdentifier<ConnectionId> {
+        InboundIdentifier("position".into(), ConnectionId(0))
+    }
+
+    fn rejections(server: &mut TestServer) -> Vec<HandshakeError> {
+        server
+            .drain_events()
+            .filter_map(|event| match event {
+                ServerEvent::HandshakeRejected(_, error) => Some(error),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn attempt(server: &mut TestServer, client: &mut TestClient) {
+        client.handshake();
+        testing::pump(server, &mut [client]);
+    }
+
+    #[test]
+    fn inbound_resources_are_declared_before_connecting() {
+        let mut server = testing::server();
+        server.register_inbound_with("position".into(), 0u32, FullOnly);
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_outbound_with("position".into(), 0u32, FullOnly);
+        testing::connect(&mut server, &mut client);
+
+        **client
+            .resources_mut()
+            .outbound_mut::<u32>("position".into())
+            .unwrap() = 7;
+        testing::pump(&mut server, &mut [&mut client]);
+        let received = server.resources().inbound::<u32>(position()).unwrap();
+        assert_eq!(**received, 7);
+    }
+
+    #[test]
+    fn schema_mismatch_is_rejected_and_the_connection_dropped() {
+        let mut server = testing::server();
+        server.register_inbound_with("position".into(), 0u32, FullOnly);
+        let mut client = testing::client(&mut server);
+        attempt(&mut server, &mut client);
+
+        assert!(matches!(
+            client.state(),
+            ConnectionState::Rejected(HandshakeError::Schema { .. })
+        ));
+        assert_eq!(rejections(&mut server).len(), 1);
+        assert!(!server.is_connected(ConnectionId(0)));
+        assert!(server.resources().inbound::<u32>(position()).is_none());
+    }
+
+    #[test]
+    fn application_id_mismatch_is_rejected() {
+        let mut server = testing::server();
+        let mut client = testing::client(&mut server);
+        client.set_application_id("other".into());
+        attempt(&mut server, &mut client);
+
+        let expected = HandshakeError::ApplicationId {
+            expected: String::new(),
+            found: "other".into(),
+        };
+        assert_eq!(rejections(&mut server), vec![expected.clone()]);
+        assert_eq!(client.state(), &ConnectionState::Rejected(expected));
+    }
+
+    #[test]
+    fn rejected_reconnection_stops_the_state() {
+        let mut server = testing::server();
+        server
+            .resources_mut()
+            .register_outbound_with("score".into(), 0u32, FullOnly);
+        let mut client = testing::client(&mut server);
+        client
+            .resources_mut()
+            .register_inbound_with("score".into(), 0u32, FullOnly);
+        testing::connect(&mut server, &mut client);
+
+        client.set_application_id("other".into());
+        attempt(&mut server, &mut client);
+        assert!(!server.is_connected(ConnectionId(0)));
+
+        **server
+            .resources_mut()
+            .outbound_mut::<u32>("score".into())
+            .unwrap() = 3;
+        testing::pump(&mut server, &mut [&mut client]);
+        let received = client.resources().inbound::<u32>("score".into()).unwrap();
+        assert_eq!(**received, 0);
+    }
+
+    #[test]
+    fn schema_leaves_out_module_paths() {
+        let mut first = Schema::default();
+        first.declare::<Vec<crate::transport::ConnectionId>>("connections");
+        let mut second = Schema::default();
+        second.declare::<Vec<ConnectionId>>("connections");
+        assert_eq!(first.type_name("connections"), Some("Vec<ConnectionId>"));
+        assert_eq!(first.hash(), second.hash());
+    }
+
+    #[test]
+    fn ids_are_kept_when_declaring_more() {
+        let mut schema = Schema::default();
+        schema.declare::<u32>("b");
+        schema.declare::<u32>("a");
+        schema.declare::<String>("b");
+        schema.declare::<u32>("c");
+        assert_eq!(schema.id("b"), Some(0));
+        assert_eq!(schema.id("a"), Some(1));
+        assert_eq!(schema.id("c"), Some(2));
+        assert_eq!(schema.identifier(1), Some("a"));
+        assert_eq!(schema.type_name("b"), Some("String"));
+    }
+
+    #[test]
+    fn peers_declaring_in_another_order_use_the_server_ids() {
+        let mut server = testing::server();
+        server.register_message::<u32>("number".into(), Reliability::ReliableOrdered);
+        server.register_message::<String>("text".into(), Reliability::ReliableOrdered);
+        let mut client = testing::client(&mut server);
+        client.register_message::<String>("text".into(), Reliability::ReliableOrdered);
+        client.register_message::<u32>("number".into(), Reliability::ReliableOrdered);
+        testing::connect(&mut server, &mut client);
+
+        client.send_message(5u32).unwrap();
+        client.send_message(String::from("five")).unwrap();
+        server.send_message(Target::All, 6u32).unwrap();
+        testing::pump(&mut server, &mut [&mut client]);
+        let numbers: Vec<_> = server.drain_messages::<u32>().collect();
+        let texts: Vec<_> = server.drain_messages::<String>().collect();
+        assert_eq!(numbers, vec![(ConnectionId(0), 5)]);
+        assert_eq!(texts, vec![(ConnectionId(0), "five".to_string())]);
+        assert_eq!(client.drain_messages::<u32>().collect::<Vec<_>>(), vec![6]);
+    }
+
+    #[test]
+    fn resource_packets_with_foreign_ids_are_skipped() {
+        let mut server = testing::server();
+        server.register_message::<u32>("number".into(), Reliability::ReliableOrdered);
+        let mut client = testing::client(&mut server);
+        client.register_message::<u32>("number".into(), Reliability::ReliableOrdered);
+        testing::connect(&mut server, &mut client);
+
+        let id = server.resources().schema().id("number").unwrap();
+        for id in [id, 99].iter().copied() {
+            let mut packet = Vec::new();
+            ResourcePacket::write_header(PacketKind::Resource, id, &mut packet);
+            packet.push(0);
+            server.synchronize_inbound(ConnectionId(0), packet.clone());
+            client.synchronize_inbound(packet);
+        }
+        assert!(server.is_connected(ConnectionId(0)));
+        assert!(client.is_connected());
+    }
+}
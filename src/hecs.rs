@@ -0,0 +1,136 @@
+use crate::replication::{ComponentAccess, ReplicatedWorld};
+use hecs::{Component, Entity, World};
+
+// Entities with this marker are replicated, and entities spawned for received ones get it too.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Replicated;
+
+impl ReplicatedWorld for World {
+    type Entity = Entity;
+
+    fn replicated_entities(&self, entities: &mut Vec<Entity>) {
+        let mut query = self.query::<&Replicated>();
+        entities.extend(query.iter().map(|(entity, _)| entity));
+    }
+
+    fn spawn(&mut self) -> Entity {
+        World::spawn(self, (Replicated,))
+    }
+
+    fn despawn(&mut self, entity: Entity) {
+        let _ = World::despawn(self, entity);
+    }
+}
+
+impl<T: Component> ComponentAccess<T> for World {
+    fn read_component(&self, entity: Entity, read: &mut dyn FnMut(&T)) -> bool {
+        match self.get::<&T>(entity) {
+            Ok(component) => {
+                read(&component);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn insert_component(&mut self, entity: Entity, component: T) {
+        let _ = self.insert_one(entity, component);
+    }
+
+    fn remove_component(&mut self, entity: Entity) {
+        let _ = self.remove_one::<T>(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replication::{EntityMirror, EntityReplicator};
+    use crate::resources::Resources;
+    use crate::testing::{self, TestClient, TestServer};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    struct Position(f32, f32);
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Name(String);
+
+    fn positions(world: &World) -> Vec<Position> {
+        let mut query = world.query::<(&Replicated, &Position)>();
+        let mut positions = query.iter().map(|(_, (_, p))| *p).collect::<Vec<_>>();
+        positions.sort_by(|a, b| a.0.total_cmp(&b.0));
+        positions
+    }
+
+    struct Replication {
+        server: TestServer,
+        client: TestClient,
+        replicator: EntityReplicator<World>,
+        mirror: EntityMirror<World>,
+    }
+
+    impl Replication {
+        fn new() -> Self {
+            let mut server = testing::server();
+            let mut replicator = EntityReplicator::new("entities");
+            let resources = server.resources_mut();
+            replicator.register_component::<Position, _, _, _>("position", resources);
+            replicator.register_component::<Name, _, _, _>("name", resources);
+            replicator.register_outbound(resources);
+            let mut client = testing::client(&mut server);
+            let mut mirror = EntityMirror::new("entities");
+            let resources = client.resources_mut();
+            mirror.register_component::<Position, _, _, _>("position", resources);
+            mirror.register_component::<Name, _, _, _>("name", resources);
+            mirror.register_inbound(resources);
+            testing::connect(&mut server, &mut client);
+            Self {
+                server,
+                client,
+                replicator,
+                mirror,
+            }
+        }
+
+        fn tick(&mut self, source: &World, target: &mut World) {
+            let resources = self.server.resources_mut();
+            let resource = resources.outbound_mut("entities".into()).unwrap();
+            self.replicator.update(source, resource);
+            testing::pump(&mut self.server, &mut [&mut self.client]);
+            let resources = self.client.resources();
+            let resource = resources.inbound("entities".into()).unwrap();
+            self.mirror.update(target, resource);
+        }
+
+        fn mirrored(&self, entity: Entity) -> Entity {
+            let id = self.replicator.network_id(entity).unwrap();
+            self.mirror.entity(id).unwrap()
+        }
+    }
+
+    #[test]
+    fn marked_entities_are_replicated() {
+        let mut replication = Replication::new();
+        let mut source = World::new();
+        let mut target = World::new();
+        let player = source.spawn((Replicated, Position(1.0, 2.0), Name("player".into())));
+        source.spawn((Position(9.0, 9.0),));
+
+        replication.tick(&source, &mut target);
+        assert_eq!(target.len(), 1);
+        assert_eq!(positions(&target), vec![Position(1.0, 2.0)]);
+        let entity = replication.mirrored(player);
+        assert_eq!(*target.get::<&Name>(entity).unwrap(), Name("player".into()));
+
+        *source.get::<&mut Position>(player).unwrap() = Position(3.0, 2.0);
+        source.remove_one::<Name>(player).unwrap();
+        replication.tick(&source, &mut target);
+        assert_eq!(positions(&target), vec![Position(3.0, 2.0)]);
+        assert!(target.get::<&Name>(entity).is_err());
+
+        source.despawn(player).unwrap();
+        replication.tick(&source, &mut target);
+        assert_eq!(target.len(), 0);
+    }
+}
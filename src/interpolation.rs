@@ -0,0 +1,31 @@
+use crate::quantize::{FloatRange, Quantized};
+
+// Blends two received states, `t` going from 0 (self) to 1 (other).
+pub trait Interpolate {
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t as f64
+    }
+}
+
+impl<R: FloatRange> Interpolate for Quantized<R> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        Quantized::new(self.get().interpolate(&other.get(), t))
+    }
+}
+
+impl<T: Interpolate, const N: usize> Interpolate for [T; N] {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        let mut values = self.iter().zip(other).map(|(a, b)| a.interpolate(b, t));
+        [(); N].map(|_| values.next().unwrap())
+    }
+}
@@ -1,12 +1,68 @@
 use laminar::Socket;
-use laminar::{self, Packet};
+use laminar::{self, Packet, SocketEvent};
 use std::net::SocketAddr;
+use std::time::Instant;
 
-use crate::SynchronizeOutbound;
+use crate::transport::{Listen, ListenerEvent};
+use crate::{Reliability, SynchronizeInbound, SynchronizeOutbound};
 
 impl SynchronizeOutbound<SocketAddr> for Socket {
-    fn synchronize(&mut self, bound: SocketAddr, data: Vec<u8>) {
-        self.send(Packet::reliable_ordered(bound, data.clone(), None))
+    fn synchronize(&mut self, bound: SocketAddr, data: &[u8]) {
+        self.send(Packet::reliable_ordered(bound, data.to_vec(), None))
             .unwrap();
     }
+
+    fn synchronize_with(&mut self, bound: SocketAddr, data: &[u8], reliability: Reliability) {
+        let data = data.to_vec();
+        let packet = match reliability {
+            Reliability::Unreliable => Packet::unreliable(bound, data),
+            Reliability::UnreliableSequenced => Packet::unreliable_sequenced(bound, data, None),
+            Reliability::ReliableUnordered => Packet::reliable_unordered(bound, data),
+            Reliability::ReliableOrdered => Packet::reliable_ordered(bound, data, None),
+            Reliability::ReliableSequenced => Packet::reliable_sequenced(bound, data, None),
+        };
+        self.send(packet).unwrap();
+    }
+}
+
+// The socket is polled once its queue is drained. Only packets are returned, connection events
+// are discarded.
+impl SynchronizeInbound<SocketAddr> for Socket {
+    fn receive(&mut self) -> Option<(SocketAddr, Vec<u8>)> {
+        let mut polled = false;
+        loop {
+            match Socket::recv(self) {
+                Some(SocketEvent::Packet(packet)) => {
+                    return Some((packet.addr(), packet.payload().to_vec()))
+                }
+                Some(_) => {}
+                None if polled => return None,
+                None => {
+                    Socket::manual_poll(self, Instant::now());
+                    polled = true;
+                }
+            }
+        }
+    }
+}
+
+impl Listen<SocketAddr> for Socket {
+    fn manual_poll(&mut self) {
+        Socket::manual_poll(self, Instant::now());
+    }
+
+    fn recv(&mut self) -> Option<ListenerEvent<SocketAddr>> {
+        Some(match Socket::recv(self)? {
+            SocketEvent::Packet(packet) => {
+                ListenerEvent::Packet(packet.addr(), packet.payload().to_vec())
+            }
+            SocketEvent::Connect(address) => ListenerEvent::Connect(address),
+            SocketEvent::Timeout(address) | SocketEvent::Disconnect(address) => {
+                ListenerEvent::Disconnect(address)
+            }
+        })
+    }
+
+    // Laminar can't close a connection, it is forgotten once it times out.
+    fn disconnect(&mut self, _address: SocketAddr) {}
 }
@@ -0,0 +1,151 @@
+use crate::replication::{ComponentAccess, ReplicatedWorld};
+use legion::storage::Component;
+use legion::{Entity, EntityStore, IntoQuery, World};
+
+// Entities with this marker are replicated, and entities spawned for received ones get it too.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Replicated;
+
+impl ReplicatedWorld for World {
+    type Entity = Entity;
+
+    fn replicated_entities(&self, entities: &mut Vec<Entity>) {
+        let mut query = <(Entity, &Replicated)>::query();
+        entities.extend(query.iter(self).map(|(entity, _)| *entity));
+    }
+
+    fn spawn(&mut self) -> Entity {
+        self.push((Replicated,))
+    }
+
+    fn despawn(&mut self, entity: Entity) {
+        self.remove(entity);
+    }
+}
+
+impl<T: Component> ComponentAccess<T> for World {
+    fn read_component(&self, entity: Entity, read: &mut dyn FnMut(&T)) -> bool {
+        let entry = match self.entry_ref(entity) {
+            Ok(entry) => entry,
+            Err(_) => return false,
+        };
+        match entry.get_component::<T>() {
+            Ok(component) => {
+                read(component);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn insert_component(&mut self, entity: Entity, component: T) {
+        if let Some(mut entry) = self.entry(entity) {
+            entry.add_component(component);
+        }
+    }
+
+    fn remove_component(&mut self, entity: Entity) {
+        if let Some(mut entry) = self.entry(entity) {
+            entry.remove_component::<T>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replication::{EntityMirror, EntityReplicator};
+    use crate::resources::Resources;
+    use crate::testing::{self, TestClient, TestServer};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    struct Position(f32, f32);
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Name(String);
+
+    fn positions(world: &World) -> Vec<Position> {
+        let mut query = <(&Replicated, &Position)>::query();
+        let mut positions = query.iter(world).map(|(_, p)| *p).collect::<Vec<_>>();
+        positions.sort_by(|a, b| a.0.total_cmp(&b.0));
+        positions
+    }
+
+    struct Replication {
+        server: TestServer,
+        client: TestClient,
+        replicator: EntityReplicator<World>,
+        mirror: EntityMirror<World>,
+    }
+
+    impl Replication {
+        fn new() -> Self {
+            let mut server = testing::server();
+            let mut replicator = EntityReplicator::new("entities");
+            let resources = server.resources_mut();
+            replicator.register_component::<Position, _, _, _>("position", resources);
+            replicator.register_component::<Name, _, _, _>("name", resources);
+            replicator.register_outbound(resources);
+            let mut client = testing::client(&mut server);
+            let mut mirror = EntityMirror::new("entities");
+            let resources = client.resources_mut();
+            mirror.register_component::<Position, _, _, _>("position", resources);
+            mirror.register_component::<Name, _, _, _>("name", resources);
+            mirror.register_inbound(resources);
+            testing::connect(&mut server, &mut client);
+            Self {
+                server,
+                client,
+                replicator,
+                mirror,
+            }
+        }
+
+        fn tick(&mut self, source: &World, target: &mut World) {
+            let resources = self.server.resources_mut();
+            let resource = resources.outbound_mut("entities".into()).unwrap();
+            self.replicator.update(source, resource);
+            testing::pump(&mut self.server, &mut [&mut self.client]);
+            let resources = self.client.resources();
+            let resource = resources.inbound("entities".into()).unwrap();
+            self.mirror.update(target, resource);
+        }
+
+        fn mirrored(&self, entity: Entity) -> Entity {
+            let id = self.replicator.network_id(entity).unwrap();
+            self.mirror.entity(id).unwrap()
+        }
+    }
+
+    #[test]
+    fn marked_entities_are_replicated() {
+        let mut replication = Replication::new();
+        let mut source = World::default();
+        let mut target = World::default();
+        let player = source.push((Replicated, Position(1.0, 2.0), Name("player".into())));
+        source.push((Position(9.0, 9.0),));
+
+        replication.tick(&source, &mut target);
+        assert_eq!(target.len(), 1);
+        assert_eq!(positions(&target), vec![Position(1.0, 2.0)]);
+        let entity = replication.mirrored(player);
+        let entry = target.entry_ref(entity).unwrap();
+        assert_eq!(
+            entry.get_component::<Name>().ok(),
+            Some(&Name("player".into()))
+        );
+
+        let mut entry = source.entry(player).unwrap();
+        *entry.get_component_mut::<Position>().unwrap() = Position(3.0, 2.0);
+        entry.remove_component::<Name>();
+        replication.tick(&source, &mut target);
+        assert_eq!(positions(&target), vec![Position(3.0, 2.0)]);
+        let entry = target.entry_ref(entity).unwrap();
+        assert!(entry.get_component::<Name>().is_err());
+
+        source.remove(player);
+        replication.tick(&source, &mut target);
+        assert_eq!(target.len(), 0);
+    }
+}
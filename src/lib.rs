@@ -1,11 +1,52 @@
 pub mod client;
+pub mod codec;
+pub mod collections;
+pub mod compression;
+pub mod diff;
+pub mod handshake;
+pub mod interpolation;
+pub mod messages;
 pub mod node;
+pub mod quantize;
+pub mod replication;
 pub mod resources;
+pub mod rpc;
 pub mod server;
+pub mod tracked;
+pub mod transport;
+pub mod validation;
+
+#[cfg(feature = "bevy")]
+pub mod bevy;
+
+#[cfg(feature = "hecs")]
+pub mod hecs;
 
 #[cfg(feature = "laminar")]
 pub mod laminar;
 
+#[cfg(feature = "legion")]
+pub mod legion;
+
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
+
+#[cfg(test)]
+mod testing;
+mod wire;
+
+#[cfg(feature = "derive")]
+pub use rayzo_derive::Networked;
+
+// Used by the derive macros so that dependents don't need serde themselves.
+#[doc(hidden)]
+pub use serde;
+
+pub(crate) use wire::{PacketKind, ResourcePacket};
+
 use serde::{Deserialize, Serialize};
 use std::hash::Hash;
 
@@ -15,12 +56,24 @@ pub enum Target<I> {
     All,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Reliability {
+    Unreliable,
+    UnreliableSequenced,
+    ReliableUnordered,
+    ReliableOrdered,
+    ReliableSequenced,
+}
+
 pub trait SynchronizeOutbound<B> {
-    fn synchronize(&mut self, bound: B, data: Vec<u8>);
+    fn synchronize(&mut self, bound: B, data: &[u8]);
+
+    fn synchronize_with(&mut self, bound: B, data: &[u8], _reliability: Reliability) {
+        self.synchronize(bound, data);
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub(crate) struct ResourcePacket {
-    identifier: String,
-    data: Vec<u8>,
+// Nodes that can be polled for received packets, returning `None` once drained.
+pub trait SynchronizeInbound<B> {
+    fn receive(&mut self) -> Option<(B, Vec<u8>)>;
 }
@@ -0,0 +1,197 @@
+use crate::codec::{Codec, DefaultCodec};
+use crate::Reliability;
+use downcast_rs::{impl_downcast, Downcast};
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageError {
+    // No message was registered for the type.
+    Unregistered,
+}
+
+pub struct Messages<B, K: Codec = DefaultCodec> {
+    identifiers: HashMap<TypeId, (String, Reliability)>,
+    inbound_messages: HashMap<String, Box<dyn InternalInboundMessages<B, K>>>,
+}
+
+impl<B, K: Codec> Default for Messages<B, K> {
+    fn default() -> Self {
+        Self {
+            identifiers: HashMap::new(),
+            inbound_messages: HashMap::new(),
+        }
+    }
+}
+
+impl<B: 'static + Send, K: Codec> Messages<B, K> {
+    pub(crate) fn register<T>(&mut self, identifier: String, reliability: Reliability)
+    where
+        T: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        self.identifiers
+            .insert(TypeId::of::<T>(), (identifier.clone(), reliability));
+        self.inbound_messages
+            .insert(identifier, Box::new(InboundMessages::<B, T>::new()));
+    }
+
+    pub(crate) fn identifier<T>(&self) -> Result<(&str, Reliability), MessageError>
+    where
+        T: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        let (identifier, reliability) = self
+            .identifiers
+            .get(&TypeId::of::<T>())
+            .ok_or(MessageError::Unregistered)?;
+        Ok((identifier, *reliability))
+    }
+
+    pub(crate) fn deserialize(&mut self, sender: B, identifier: &str, data: &[u8]) {
+        if let Some(messages) = self.inbound_messages.get_mut(identifier) {
+            messages.deserialize(sender, data);
+        }
+    }
+
+    // Yields nothing when the type isn't registered.
+    pub(crate) fn drain<T>(&mut self) -> impl Iterator<Item = (B, T)> + '_
+    where
+        T: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        let inbound_messages = &mut self.inbound_messages;
+        self.identifiers
+            .get(&TypeId::of::<T>())
+            .and_then(move |(identifier, _)| inbound_messages.get_mut(identifier))
+            .and_then(|b| b.downcast_mut::<InboundMessages<B, T>>())
+            .map(|inbound| inbound.messages.drain(..))
+            .into_iter()
+            .flatten()
+    }
+}
+
+pub(crate) trait InternalInboundMessages<B, K: Codec>: Downcast + Send {
+    fn deserialize(&mut self, sender: B, data: &[u8]);
+}
+
+impl_downcast!(InternalInboundMessages<B, K> where K: Codec);
+
+struct InboundMessages<B, T> {
+    messages: Vec<(B, T)>,
+}
+
+impl<B, T> InboundMessages<B, T> {
+    fn new() -> Self {
+        Self {
+            messages: Vec::new(),
+        }
+    }
+}
+
+impl<B: 'static, T: 'static, K> InternalInboundMessages<B, K> for InboundMessages<B, T>
+where
+    B: Send,
+    T: Debug + DeserializeOwned + Send,
+    K: Codec,
+{
+    fn deserialize(&mut self, sender: B, data: &[u8]) {
+        if let Some(message) = K::deserialize::<T>(data) {
+            self.messages.push((sender, message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessageError;
+    use crate::codec::DefaultCodec;
+    use crate::testing;
+    use crate::transport::ConnectionId;
+    use crate::{PacketKind, Reliability, ResourcePacket, Target};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Chat(String);
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Ping(u32);
+
+    #[test]
+    fn messages_are_delivered_in_both_directions() {
+        let mut server = testing::server();
+        server.register_message::<Chat>("chat".into(), Reliability::ReliableOrdered);
+        server.register_message::<Ping>("ping".into(), Reliability::Unreliable);
+        let mut first = testing::client(&mut server);
+        let mut second = testing::client(&mut server);
+        for client in [&mut first, &mut second] {
+            client.register_message::<Chat>("chat".into(), Reliability::ReliableOrdered);
+            client.register_message::<Ping>("ping".into(), Reliability::Unreliable);
+            testing::connect(&mut server, client);
+        }
+
+        first.send_message(Chat("hello".into())).unwrap();
+        first.send_message(Ping(1)).unwrap();
+        second.send_message(Chat("world".into())).unwrap();
+        testing::pump(&mut server, &mut [&mut first, &mut second]);
+        let mut chats = server.drain_messages::<Chat>().collect::<Vec<_>>();
+        chats.sort_by_key(|(connection, _)| *connection);
+        assert_eq!(
+            chats,
+            vec![
+                (ConnectionId(0), Chat("hello".into())),
+                (ConnectionId(1), Chat("world".into())),
+            ]
+        );
+        let pings = server.drain_messages::<Ping>().collect::<Vec<_>>();
+        assert_eq!(pings, vec![(ConnectionId(0), Ping(1))]);
+
+        server
+            .send_message(Target::All, Chat("everyone".into()))
+            .unwrap();
+        server
+            .send_message(Target::Specific(ConnectionId(1)), Ping(2))
+            .unwrap();
+        testing::pump(&mut server, &mut [&mut first, &mut second]);
+        let chats = first.drain_messages::<Chat>().collect::<Vec<_>>();
+        assert_eq!(chats, vec![Chat("everyone".into())]);
+        assert_eq!(first.drain_messages::<Ping>().count(), 0);
+        let chats = second.drain_messages::<Chat>().collect::<Vec<_>>();
+        assert_eq!(chats, vec![Chat("everyone".into())]);
+        assert_eq!(
+            second.drain_messages::<Ping>().collect::<Vec<_>>(),
+            vec![Ping(2)]
+        );
+    }
+
+    #[test]
+    fn messages_from_unknown_connections_are_dropped() {
+        let mut server = testing::server();
+        server.register_message::<Chat>("chat".into(), Reliability::ReliableOrdered);
+        let mut client = testing::client(&mut server);
+        client.register_message::<Chat>("chat".into(), Reliability::ReliableOrdered);
+        testing::connect(&mut server, &mut client);
+        server.remove_connection(ConnectionId(0));
+
+        let mut packet = Vec::new();
+        let id = server.resources().schema().id("chat").unwrap();
+        let chat = Chat("x".into());
+        ResourcePacket::write::<DefaultCodec, _>(PacketKind::Message, id, &chat, &mut packet);
+        server.synchronize_inbound(ConnectionId(0), packet);
+        assert_eq!(server.drain_messages::<Chat>().count(), 0);
+    }
+
+    #[test]
+    fn unregistered_messages_are_not_sent() {
+        let mut server = testing::server();
+        let mut client = testing::client(&mut server);
+        testing::connect(&mut server, &mut client);
+
+        let sent = client.send_message(Chat("x".into()));
+        assert_eq!(sent, Err(MessageError::Unregistered));
+        let sent = server.send_message(Target::All, Chat("x".into()));
+        assert_eq!(sent, Err(MessageError::Unregistered));
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(server.drain_messages::<Chat>().count(), 0);
+        assert_eq!(client.drain_messages::<Chat>().count(), 0);
+    }
+}
@@ -27,7 +27,7 @@ where
         Self {
             node,
             resources: R::default(),
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
         }
     }
 
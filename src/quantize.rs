@@ -0,0 +1,536 @@
+use serde::de::{self, DeserializeSeed, SeqAccess, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_diff::{ApplyContext, DiffContext, SerdeDiff};
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Formatter};
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+pub trait FloatRange {
+    const MIN: f32;
+    const MAX: f32;
+    const PRECISION: f32;
+}
+
+pub trait QuaternionPrecision {
+    const BITS: u8;
+}
+
+pub struct Quantized<R> {
+    value: f32,
+    _phantom: PhantomData<fn() -> R>,
+}
+
+impl<R: FloatRange> Quantized<R> {
+    pub fn new(value: f32) -> Self {
+        Self {
+            value: Self::dequantize(Self::quantize(value)),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn get(&self) -> f32 {
+        self.value
+    }
+
+    pub fn set(&mut self, value: f32) {
+        self.value = Self::dequantize(Self::quantize(value));
+    }
+
+    pub fn bits() -> u8 {
+        let steps = ((R::MAX - R::MIN) / R::PRECISION).ceil() as u64;
+        (64 - steps.leading_zeros()).min(32) as u8
+    }
+
+    fn quantize(value: f32) -> u32 {
+        ((value.clamp(R::MIN, R::MAX) - R::MIN) / R::PRECISION).round() as u32
+    }
+
+    fn dequantize(value: u32) -> f32 {
+        (R::MIN + value as f32 * R::PRECISION).min(R::MAX)
+    }
+}
+
+impl<R: FloatRange> Deref for Quantized<R> {
+    type Target = f32;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<R: FloatRange> From<f32> for Quantized<R> {
+    fn from(value: f32) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<R: FloatRange> Default for Quantized<R> {
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+impl<R> Clone for Quantized<R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<R> Copy for Quantized<R> {}
+
+impl<R> Debug for Quantized<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<R: FloatRange> PartialEq for Quantized<R> {
+    fn eq(&self, other: &Self) -> bool {
+        Self::quantize(self.value) == Self::quantize(other.value)
+    }
+}
+
+impl<R: FloatRange> Serialize for Quantized<R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        BitField::new(Self::quantize(self.value), Self::bits()).serialize(serializer)
+    }
+}
+
+impl<'de, R: FloatRange> Deserialize<'de> for Quantized<R> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = BitFieldSeed(Self::bits()).deserialize(deserializer)?;
+        Ok(Self {
+            value: Self::dequantize(value),
+            _phantom: PhantomData,
+        })
+    }
+}
+
+impl<R: FloatRange> SerdeDiff for Quantized<R> {
+    fn diff<'a, S: serde::ser::SerializeSeq>(
+        &self,
+        ctx: &mut DiffContext<'a, S>,
+        other: &Self,
+    ) -> Result<bool, S::Error> {
+        if self != other {
+            ctx.save_value(other)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn apply<'de, A>(
+        &mut self,
+        seq: &mut A,
+        ctx: &mut ApplyContext,
+    ) -> Result<bool, <A as SeqAccess<'de>>::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        ctx.read_value(seq, self)
+    }
+}
+
+// Unit quaternion stored as its three smallest components plus the index of the largest one.
+pub struct SmallestThree<P> {
+    value: [f32; 4],
+    _phantom: PhantomData<fn() -> P>,
+}
+
+impl<P: QuaternionPrecision> SmallestThree<P> {
+    const MAX: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    pub fn new(value: [f32; 4]) -> Self {
+        Self {
+            value: Self::decode(Self::encode(value)),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn get(&self) -> [f32; 4] {
+        self.value
+    }
+
+    pub fn set(&mut self, value: [f32; 4]) {
+        self.value = Self::decode(Self::encode(value));
+    }
+
+    fn steps() -> f32 {
+        ((1u64 << P::BITS) - 1) as f32
+    }
+
+    fn encode(mut value: [f32; 4]) -> [u32; 4] {
+        // NaN components would otherwise spread to the decoded largest one.
+        for component in value.iter_mut().filter(|c| c.is_nan()) {
+            *component = 0.0;
+        }
+        let largest = (0..4)
+            .max_by(|a, b| value[*a].abs().total_cmp(&value[*b].abs()))
+            .unwrap();
+        let sign = if value[largest] < 0.0 { -1.0 } else { 1.0 };
+        let mut encoded = [largest as u32, 0, 0, 0];
+        for (slot, component) in (0..4).filter(|i| *i != largest).enumerate() {
+            let component = (value[component] * sign).clamp(-Self::MAX, Self::MAX);
+            encoded[slot + 1] =
+                ((component + Self::MAX) / (2.0 * Self::MAX) * Self::steps()).round() as u32;
+        }
+        encoded
+    }
+
+    fn decode(encoded: [u32; 4]) -> [f32; 4] {
+        let largest = encoded[0] as usize;
+        let mut value = [0.0; 4];
+        let mut sum = 0.0;
+        for (slot, component) in (0..4).filter(|i| *i != largest).enumerate() {
+            let decoded = encoded[slot + 1] as f32 / Self::steps() * 2.0 * Self::MAX - Self::MAX;
+            value[component] = decoded;
+            sum += decoded * decoded;
+        }
+        value[largest] = (1.0 - sum).max(0.0).sqrt();
+        value
+    }
+}
+
+impl<P: QuaternionPrecision> Default for SmallestThree<P> {
+    fn default() -> Self {
+        Self::new([0.0, 0.0, 0.0, 1.0])
+    }
+}
+
+impl<P> Clone for SmallestThree<P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P> Copy for SmallestThree<P> {}
+
+impl<P> Debug for SmallestThree<P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<P: QuaternionPrecision> PartialEq for SmallestThree<P> {
+    fn eq(&self, other: &Self) -> bool {
+        Self::encode(self.value) == Self::encode(other.value)
+    }
+}
+
+impl<P: QuaternionPrecision> Serialize for SmallestThree<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let encoded = Self::encode(self.value);
+        let mut tuple = serializer.serialize_tuple(4)?;
+        tuple.serialize_element(&BitField::new(encoded[0], 2))?;
+        for component in &encoded[1..] {
+            tuple.serialize_element(&BitField::new(*component, P::BITS))?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de, P: QuaternionPrecision> Deserialize<'de> for SmallestThree<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SmallestThreeVisitor<P>(PhantomData<fn() -> P>);
+
+        impl<'de, P: QuaternionPrecision> Visitor<'de> for SmallestThreeVisitor<P> {
+            type Value = [u32; 4];
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str("a smallest three quaternion")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut encoded = [0; 4];
+                for (i, slot) in encoded.iter_mut().enumerate() {
+                    let bits = if i == 0 { 2 } else { P::BITS };
+                    *slot = seq
+                        .next_element_seed(BitFieldSeed(bits))?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+                Ok(encoded)
+            }
+        }
+
+        let encoded = deserializer.deserialize_tuple(4, SmallestThreeVisitor::<P>(PhantomData))?;
+        if encoded[0] > 3 {
+            return Err(de::Error::custom("invalid smallest three index"));
+        }
+        Ok(Self {
+            value: Self::decode(encoded),
+            _phantom: PhantomData,
+        })
+    }
+}
+
+impl<P: QuaternionPrecision> SerdeDiff for SmallestThree<P> {
+    fn diff<'a, S: serde::ser::SerializeSeq>(
+        &self,
+        ctx: &mut DiffContext<'a, S>,
+        other: &Self,
+    ) -> Result<bool, S::Error> {
+        if self != other {
+            ctx.save_value(other)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn apply<'de, A>(
+        &mut self,
+        seq: &mut A,
+        ctx: &mut ApplyContext,
+    ) -> Result<bool, <A as SeqAccess<'de>>::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        ctx.read_value(seq, self)
+    }
+}
+
+// Maps a plain value to its quantized wire representation, `P` being a `FloatRange` for
+// floats and vectors or a `QuaternionPrecision` for rotations.
+pub trait Quantize<P>: Sized {
+    type Wire: Serialize + DeserializeOwned;
+
+    fn quantize(&self) -> Self::Wire;
+
+    fn dequantize(wire: Self::Wire) -> Self;
+}
+
+impl<R: FloatRange> Quantize<R> for f32 {
+    type Wire = Quantized<R>;
+
+    fn quantize(&self) -> Self::Wire {
+        Quantized::new(*self)
+    }
+
+    fn dequantize(wire: Self::Wire) -> Self {
+        wire.get()
+    }
+}
+
+macro_rules! impl_quantize_vector {
+    ($($len:literal),*) => {
+        $(
+            impl<R: FloatRange> Quantize<R> for [f32; $len] {
+                type Wire = [Quantized<R>; $len];
+
+                fn quantize(&self) -> Self::Wire {
+                    let mut wire = [Quantized::default(); $len];
+                    for (wire, value) in wire.iter_mut().zip(self) {
+                        wire.set(*value);
+                    }
+                    wire
+                }
+
+                fn dequantize(wire: Self::Wire) -> Self {
+                    let mut value = [0.0; $len];
+                    for (value, wire) in value.iter_mut().zip(&wire) {
+                        *value = wire.get();
+                    }
+                    value
+                }
+            }
+        )*
+    };
+}
+
+impl_quantize_vector!(2, 3);
+
+impl<P: QuaternionPrecision> Quantize<P> for [f32; 4] {
+    type Wire = SmallestThree<P>;
+
+    fn quantize(&self) -> Self::Wire {
+        SmallestThree::new(*self)
+    }
+
+    fn dequantize(wire: Self::Wire) -> Self {
+        wire.get()
+    }
+}
+
+// The bit width travels in the newtype name so the BitPacked codec can pack the value,
+// while byte-aligned codecs just see a plain u32.
+pub(crate) const BIT_FIELDS: [&str; 33] = [
+    "rayzo::bits::0",
+    "rayzo::bits::1",
+    "rayzo::bits::2",
+    "rayzo::bits::3",
+    "rayzo::bits::4",
+    "rayzo::bits::5",
+    "rayzo::bits::6",
+    "rayzo::bits::7",
+    "rayzo::bits::8",
+    "rayzo::bits::9",
+    "rayzo::bits::10",
+    "rayzo::bits::11",
+    "rayzo::bits::12",
+    "rayzo::bits::13",
+    "rayzo::bits::14",
+    "rayzo::bits::15",
+    "rayzo::bits::16",
+    "rayzo::bits::17",
+    "rayzo::bits::18",
+    "rayzo::bits::19",
+    "rayzo::bits::20",
+    "rayzo::bits::21",
+    "rayzo::bits::22",
+    "rayzo::bits::23",
+    "rayzo::bits::24",
+    "rayzo::bits::25",
+    "rayzo::bits::26",
+    "rayzo::bits::27",
+    "rayzo::bits::28",
+    "rayzo::bits::29",
+    "rayzo::bits::30",
+    "rayzo::bits::31",
+    "rayzo::bits::32",
+];
+
+#[cfg(feature = "bitpacked")]
+pub(crate) fn bit_width(name: &str) -> Option<u8> {
+    BIT_FIELDS
+        .iter()
+        .position(|field| *field == name)
+        .map(|bits| bits as u8)
+}
+
+struct BitField {
+    value: u32,
+    bits: u8,
+}
+
+impl BitField {
+    fn new(value: u32, bits: u8) -> Self {
+        Self { value, bits }
+    }
+}
+
+impl Serialize for BitField {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(BIT_FIELDS[self.bits as usize], &self.value)
+    }
+}
+
+struct BitFieldSeed(u8);
+
+impl<'de> DeserializeSeed<'de> for BitFieldSeed {
+    type Value = u32;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        struct BitFieldVisitor;
+
+        impl<'de> Visitor<'de> for BitFieldVisitor {
+            type Value = u32;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str("a quantized value")
+            }
+
+            fn visit_newtype_struct<D: Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                u32::deserialize(deserializer)
+            }
+
+            fn visit_u32<E: de::Error>(self, value: u32) -> Result<Self::Value, E> {
+                Ok(value)
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                u32::try_from(value)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+            }
+        }
+
+        let bits = self.0;
+        let value =
+            deserializer.deserialize_newtype_struct(BIT_FIELDS[bits as usize], BitFieldVisitor)?;
+        if bits < 32 && value >> bits != 0 {
+            let unexpected = de::Unexpected::Unsigned(value as u64);
+            return Err(de::Error::invalid_value(
+                unexpected,
+                &"a value within the bit width",
+            ));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{Codec, DefaultCodec};
+    use serde::de::value::{Error, U64Deserializer};
+    use serde::de::IntoDeserializer;
+
+    struct Unit;
+
+    impl FloatRange for Unit {
+        const MIN: f32 = -1.0;
+        const MAX: f32 = 1.0;
+        const PRECISION: f32 = 0.01;
+    }
+
+    struct Fine;
+
+    impl QuaternionPrecision for Fine {
+        const BITS: u8 = 12;
+    }
+
+    fn round_trip<T: Serialize + DeserializeOwned>(value: &T) -> T {
+        DefaultCodec::deserialize(&DefaultCodec::serialize(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn quantized_values_are_clamped_to_their_range() {
+        assert_eq!(Quantized::<Unit>::bits(), 8);
+        assert!((Quantized::<Unit>::new(0.123).get() - 0.12).abs() < 1e-6);
+        assert_eq!(Quantized::<Unit>::new(5.0).get(), 1.0);
+        assert_eq!(Quantized::<Unit>::new(-5.0).get(), -1.0);
+        assert_eq!(Quantized::<Unit>::new(f32::NAN).get(), -1.0);
+        let value = Quantized::<Unit>::new(0.5);
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn smallest_three_keeps_the_rotation() {
+        let norm = (0.1f32 * 0.1 + 0.2 * 0.2 + 0.3 * 0.3 + 0.9 * 0.9).sqrt();
+        let rotation = [0.1 / norm, -0.2 / norm, 0.3 / norm, -0.9 / norm];
+        let decoded = round_trip(&SmallestThree::<Fine>::new(rotation)).get();
+        // The largest component is sent positive, which is the same rotation negated.
+        for (decoded, original) in decoded.iter().zip(rotation.iter()) {
+            assert!((decoded + original).abs() < 1e-3, "{:?}", decoded);
+        }
+    }
+
+    #[test]
+    fn smallest_three_accepts_nan() {
+        let value = SmallestThree::<Fine>::new([f32::NAN, 0.0, 0.0, 1.0]).get();
+        assert!(
+            value[0].abs() < 1e-3 && (value[3] - 1.0).abs() < 1e-3,
+            "{:?}",
+            value
+        );
+        let value = SmallestThree::<Fine>::new([f32::NAN; 4]).get();
+        assert!(value.iter().all(|c| c.is_finite()));
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let deserialize = |value: u64, bits| {
+            let deserializer: U64Deserializer<Error> = value.into_deserializer();
+            BitFieldSeed(bits).deserialize(deserializer)
+        };
+        assert_eq!(deserialize(255, 8).unwrap(), 255);
+        assert!(deserialize(256, 8).is_err());
+        assert_eq!(deserialize(u32::MAX as u64, 32).unwrap(), u32::MAX);
+        assert!(deserialize(u32::MAX as u64 + 1, 32).is_err());
+    }
+}
@@ -0,0 +1,564 @@
+use crate::codec::{Codec, DefaultCodec};
+use crate::diff::{apply_xor, write_xor, DiffStrategy};
+use crate::resources::{HashMapResources, InboundResource, OutboundResource, Resources};
+use crate::wire::{read_varint, write_varint};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+// Guards against diffs claiming an absurd amount of entities or components.
+const MAX_COUNT: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NetworkId(pub u32);
+
+// Every replicated entity with its components, encoded and keyed by component id. Component ids
+// are the positions of the component names in sorted order, so both ends agree on them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Entities {
+    entities: BTreeMap<NetworkId, BTreeMap<u32, Vec<u8>>>,
+}
+
+impl Entities {
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn contains(&self, id: NetworkId) -> bool {
+        self.entities.contains_key(&id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = NetworkId> + '_ {
+        self.entities.keys().copied()
+    }
+}
+
+// Despawned entities, then for each spawned or changed entity its removed components and its
+// added or changed components, XORed against their previous encoding.
+#[derive(Debug, Default)]
+pub struct EntityDelta {
+    scratch: Vec<u8>,
+}
+
+// The buffer is scratch space, so clones start empty.
+impl Clone for EntityDelta {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl DiffStrategy<Entities> for EntityDelta {
+    fn diff(&mut self, old: &Entities, new: &Entities, buffer: &mut Vec<u8>) -> Option<bool> {
+        let despawned = old.entities.keys().filter(|id| !new.contains(**id));
+        let changed = new
+            .entities
+            .iter()
+            .filter(|(id, components)| old.entities.get(id) != Some(components));
+        let despawned_count = despawned.clone().count();
+        let changed_count = changed.clone().count();
+        if despawned_count == 0 && changed_count == 0 {
+            return Some(false);
+        }
+        write_varint(despawned_count as u32, buffer);
+        for id in despawned {
+            write_varint(id.0, buffer);
+        }
+        write_varint(changed_count as u32, buffer);
+        let empty = BTreeMap::new();
+        for (id, components) in changed {
+            let previous = old.entities.get(id).unwrap_or(&empty);
+            write_varint(id.0, buffer);
+            let removed = previous.keys().filter(|c| !components.contains_key(c));
+            write_varint(removed.clone().count() as u32, buffer);
+            for component in removed {
+                write_varint(*component, buffer);
+            }
+            let updated = components
+                .iter()
+                .filter(|(c, data)| previous.get(c) != Some(data));
+            write_varint(updated.clone().count() as u32, buffer);
+            for (component, data) in updated {
+                let previous = previous.get(component).map_or(&[][..], Vec::as_slice);
+                self.scratch.clear();
+                write_xor(previous, data, &mut self.scratch);
+                write_varint(*component, buffer);
+                write_varint(self.scratch.len() as u32, buffer);
+                buffer.extend_from_slice(&self.scratch);
+            }
+        }
+        Some(true)
+    }
+
+    fn apply(&mut self, mut diff: &[u8], target: &mut Entities) -> Option<()> {
+        let diff = &mut diff;
+        for _ in 0..read_count(diff)? {
+            target.entities.remove(&NetworkId(read_varint(diff)?));
+        }
+        for _ in 0..read_count(diff)? {
+            let id = NetworkId(read_varint(diff)?);
+            let components = target.entities.entry(id).or_default();
+            for _ in 0..read_count(diff)? {
+                components.remove(&read_varint(diff)?);
+            }
+            for _ in 0..read_count(diff)? {
+                let component = read_varint(diff)?;
+                let len = read_varint(diff)? as usize;
+                if len > diff.len() {
+                    return None;
+                }
+                apply_xor(&diff[..len], components.entry(component).or_default())?;
+                *diff = &diff[len..];
+            }
+        }
+        Some(())
+    }
+}
+
+fn read_count(data: &mut &[u8]) -> Option<usize> {
+    let count = read_varint(data)? as usize;
+    if count > MAX_COUNT {
+        return None;
+    }
+    Some(count)
+}
+
+// Implemented by ECS adapters. Only entities carrying the adapter's marker are replicated.
+pub trait ReplicatedWorld {
+    type Entity: Copy + Eq + Hash;
+
+    fn replicated_entities(&self, entities: &mut Vec<Self::Entity>);
+
+    fn spawn(&mut self) -> Self::Entity;
+
+    fn despawn(&mut self, entity: Self::Entity);
+}
+
+pub trait ComponentAccess<T>: ReplicatedWorld {
+    // Calls `read` with the component if the entity has one.
+    fn read_component(&self, entity: Self::Entity, read: &mut dyn FnMut(&T)) -> bool;
+
+    fn insert_component(&mut self, entity: Self::Entity, component: T);
+
+    fn remove_component(&mut self, entity: Self::Entity);
+}
+
+type Encoder<W> = Box<dyn Fn(&W, <W as ReplicatedWorld>::Entity, &mut Vec<u8>) -> bool + Send>;
+
+// Scans the world each tick and stores the result in an outbound `Entities` resource, which
+// is only marked dirty when something changed. Component encodings are written into the
+// buffers of the previous scan.
+pub struct EntityReplicator<W: ReplicatedWorld, K = DefaultCodec> {
+    identifier: String,
+    components: BTreeMap<String, Encoder<W>>,
+    ids: HashMap<W::Entity, NetworkId>,
+    next_ids: HashMap<W::Entity, NetworkId>,
+    next_id: u32,
+    scanned: Vec<W::Entity>,
+    live: HashSet<NetworkId>,
+    scratch: Entities,
+    _phantom: PhantomData<fn() -> K>,
+}
+
+impl<W: ReplicatedWorld> EntityReplicator<W> {
+    pub fn new(identifier: &str) -> Self {
+        Self::with_codec(identifier)
+    }
+}
+
+impl<W: ReplicatedWorld, K: Codec> EntityReplicator<W, K> {
+    pub fn with_codec(identifier: &str) -> Self {
+        Self {
+            identifier: identifier.into(),
+            components: BTreeMap::new(),
+            ids: HashMap::new(),
+            next_ids: HashMap::new(),
+            next_id: 0,
+            scanned: Vec::new(),
+            live: HashSet::new(),
+            scratch: Entities::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    // Each component is declared under `<identifier>/<name>` so that the handshake catches
+    // mismatched registrations.
+    pub fn register_component<T, I, O, C>(
+        &mut self,
+        name: &str,
+        resources: &mut HashMapResources<I, O, C>,
+    ) where
+        T: Serialize,
+        W: ComponentAccess<T>,
+        I: Eq + Hash + AsRef<str>,
+        O: Eq + Hash + AsRef<str>,
+        C: Codec,
+    {
+        let encoder = |world: &W, entity, buffer: &mut Vec<u8>| {
+            world.read_component(entity, &mut |component: &T| {
+                K::serialize_into(component, buffer).unwrap();
+            })
+        };
+        self.components.insert(name.into(), Box::new(encoder));
+        let name = format!("{}/{}", self.identifier, name);
+        resources.schema_mut().declare::<T>(&name);
+    }
+
+    pub fn network_id(&self, entity: W::Entity) -> Option<NetworkId> {
+        self.ids.get(&entity).copied()
+    }
+
+    pub fn register_outbound<I, O, C>(&self, resources: &mut HashMapResources<I, O, C>)
+    where
+        I: Eq + Hash + AsRef<str>,
+        O: Eq + Hash + AsRef<str> + for<'a> From<&'a str>,
+        C: Codec,
+    {
+        let identifier = O::from(self.identifier.as_str());
+        resources.register_outbound_with(identifier, Entities::default(), EntityDelta::default());
+    }
+
+    pub fn update(&mut self, world: &W, resource: &mut OutboundResource<Entities>) {
+        self.scanned.clear();
+        world.replicated_entities(&mut self.scanned);
+        self.next_ids.clear();
+        self.live.clear();
+        for entity in self.scanned.iter().copied() {
+            let id = match self.ids.get(&entity) {
+                Some(id) => *id,
+                None => {
+                    self.next_id = self.next_id.wrapping_add(1);
+                    NetworkId(self.next_id)
+                }
+            };
+            self.next_ids.insert(entity, id);
+            self.live.insert(id);
+            let components = self.scratch.entities.entry(id).or_default();
+            for (index, encoder) in self.components.values().enumerate() {
+                let index = index as u32;
+                let buffer = components.entry(index).or_default();
+                buffer.clear();
+                if !encoder(world, entity, buffer) {
+                    components.remove(&index);
+                }
+            }
+        }
+        let live = &self.live;
+        self.scratch.entities.retain(|id, _| live.contains(id));
+        std::mem::swap(&mut self.ids, &mut self.next_ids);
+        if **resource != self.scratch {
+            std::mem::swap(&mut **resource, &mut self.scratch);
+        }
+    }
+}
+
+type Decoder<W> = Box<dyn Fn(&mut W, <W as ReplicatedWorld>::Entity, Option<&[u8]>) + Send>;
+
+// Applies a received `Entities` resource to the local world, spawning and despawning entities
+// and touching only the components that changed since the last update.
+pub struct EntityMirror<W: ReplicatedWorld, K = DefaultCodec> {
+    identifier: String,
+    components: BTreeMap<String, Decoder<W>>,
+    entities: HashMap<NetworkId, W::Entity>,
+    generation: Option<usize>,
+    applied: Entities,
+    _phantom: PhantomData<fn() -> K>,
+}
+
+impl<W: ReplicatedWorld> EntityMirror<W> {
+    pub fn new(identifier: &str) -> Self {
+        Self::with_codec(identifier)
+    }
+}
+
+impl<W: ReplicatedWorld, K: Codec> EntityMirror<W, K> {
+    pub fn with_codec(identifier: &str) -> Self {
+        Self {
+            identifier: identifier.into(),
+            components: BTreeMap::new(),
+            entities: HashMap::new(),
+            generation: None,
+            applied: Entities::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    // Components that fail to decode are removed rather than left stale.
+    pub fn register_component<T, I, O, C>(
+        &mut self,
+        name: &str,
+        resources: &mut HashMapResources<I, O, C>,
+    ) where
+        T: DeserializeOwned,
+        W: ComponentAccess<T>,
+        I: Eq + Hash + AsRef<str>,
+        O: Eq + Hash + AsRef<str>,
+        C: Codec,
+    {
+        let decoder =
+            |world: &mut W, entity, data: Option<&[u8]>| match data.and_then(K::deserialize::<T>) {
+                Some(component) => world.insert_component(entity, component),
+                None => world.remove_component(entity),
+            };
+        self.components.insert(name.into(), Box::new(decoder));
+        let name = format!("{}/{}", self.identifier, name);
+        resources.schema_mut().declare::<T>(&name);
+    }
+
+    pub fn entity(&self, id: NetworkId) -> Option<W::Entity> {
+        self.entities.get(&id).copied()
+    }
+
+    pub fn register_inbound<I, O, C>(&self, resources: &mut HashMapResources<I, O, C>)
+    where
+        I: Eq + Hash + AsRef<str> + for<'a> From<&'a str>,
+        O: Eq + Hash + AsRef<str>,
+        C: Codec,
+    {
+        let identifier = I::from(self.identifier.as_str());
+        resources.register_inbound_with(identifier, Entities::default(), EntityDelta::default());
+    }
+
+    // Does nothing unless a new generation was received since the last update.
+    pub fn update(&mut self, world: &mut W, resource: &InboundResource<Entities>) {
+        if resource.generation() != self.generation {
+            self.generation = resource.generation();
+            self.apply(world, resource);
+        }
+    }
+
+    pub fn apply(&mut self, world: &mut W, received: &Entities) {
+        let applied = &self.applied;
+        let despawned = applied.ids().filter(|id| !received.contains(*id));
+        for id in despawned.collect::<Vec<_>>() {
+            if let Some(entity) = self.entities.remove(&id) {
+                world.despawn(entity);
+            }
+        }
+        let empty = BTreeMap::new();
+        for (id, components) in received.entities.iter() {
+            let previous = self.applied.entities.get(id).unwrap_or(&empty);
+            if previous == components {
+                continue;
+            }
+            let entity = *self.entities.entry(*id).or_insert_with(|| world.spawn());
+            for (index, decoder) in self.components.values().enumerate() {
+                let index = index as u32;
+                let data = components.get(&index);
+                if data != previous.get(&index) {
+                    decoder(world, entity, data.map(Vec::as_slice));
+                }
+            }
+        }
+        self.applied.clone_from(received);
+    }
+
+    // Forgets the mapping, for instance after reconnecting, without touching the world.
+    pub fn reset(&mut self) {
+        self.entities.clear();
+        self.generation = None;
+        self.applied = Entities::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::Resources;
+    use crate::testing::{self, TestClient, TestServer};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    struct Position(f32, f32);
+
+    #[derive(Debug, Default)]
+    struct World {
+        next: u32,
+        entities: BTreeMap<u32, (Option<Position>, Option<String>)>,
+    }
+
+    impl World {
+        fn insert(&mut self, position: Option<Position>, name: Option<&str>) -> u32 {
+            let entity = self.spawn();
+            self.entities
+                .insert(entity, (position, name.map(String::from)));
+            entity
+        }
+
+        fn components(&self) -> Vec<(Option<Position>, Option<String>)> {
+            self.entities.values().cloned().collect()
+        }
+    }
+
+    impl ReplicatedWorld for World {
+        type Entity = u32;
+
+        fn replicated_entities(&self, entities: &mut Vec<u32>) {
+            entities.extend(self.entities.keys().copied());
+        }
+
+        fn spawn(&mut self) -> u32 {
+            self.next += 1;
+            self.entities.insert(self.next, (None, None));
+            self.next
+        }
+
+        fn despawn(&mut self, entity: u32) {
+            self.entities.remove(&entity);
+        }
+    }
+
+    impl ComponentAccess<Position> for World {
+        fn read_component(&self, entity: u32, read: &mut dyn FnMut(&Position)) -> bool {
+            match self.entities.get(&entity).and_then(|e| e.0.as_ref()) {
+                Some(position) => {
+                    read(position);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        fn insert_component(&mut self, entity: u32, component: Position) {
+            self.entities.get_mut(&entity).unwrap().0 = Some(component);
+        }
+
+        fn remove_component(&mut self, entity: u32) {
+            self.entities.get_mut(&entity).unwrap().0 = None;
+        }
+    }
+
+    impl ComponentAccess<String> for World {
+        fn read_component(&self, entity: u32, read: &mut dyn FnMut(&String)) -> bool {
+            match self.entities.get(&entity).and_then(|e| e.1.as_ref()) {
+                Some(name) => {
+                    read(name);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        fn insert_component(&mut self, entity: u32, component: String) {
+            self.entities.get_mut(&entity).unwrap().1 = Some(component);
+        }
+
+        fn remove_component(&mut self, entity: u32) {
+            self.entities.get_mut(&entity).unwrap().1 = None;
+        }
+    }
+
+    fn replicator(server: &mut TestServer) -> EntityReplicator<World> {
+        let mut replicator = EntityReplicator::new("entities");
+        let resources = server.resources_mut();
+        replicator.register_component::<Position, _, _, _>("position", resources);
+        replicator.register_component::<String, _, _, _>("name", resources);
+        replicator.register_outbound(resources);
+        replicator
+    }
+
+    fn mirror(client: &mut TestClient) -> EntityMirror<World> {
+        let mut mirror = EntityMirror::new("entities");
+        let resources = client.resources_mut();
+        mirror.register_component::<Position, _, _, _>("position", resources);
+        mirror.register_component::<String, _, _, _>("name", resources);
+        mirror.register_inbound(resources);
+        mirror
+    }
+
+    fn update(server: &mut TestServer, replicator: &mut EntityReplicator<World>, world: &World) {
+        let resources = server.resources_mut();
+        let resource = resources.outbound_mut("entities".into()).unwrap();
+        replicator.update(world, resource);
+    }
+
+    fn entities(server: &mut TestServer) -> &Entities {
+        let resources = server.resources_mut();
+        resources.outbound_mut("entities".into()).unwrap()
+    }
+
+    #[test]
+    fn component_types_are_declared() {
+        let mut server = testing::server();
+        replicator(&mut server);
+        let schema = server.resources().schema();
+        assert_eq!(schema.type_name("entities/position"), Some("Position"));
+        assert_eq!(schema.type_name("entities/name"), Some("String"));
+    }
+
+    #[test]
+    fn worlds_are_mirrored_through_the_server() {
+        let mut server = testing::server();
+        let mut replicator = replicator(&mut server);
+        let mut client = testing::client(&mut server);
+        let mut mirror = mirror(&mut client);
+        testing::connect(&mut server, &mut client);
+        let mut source = World::default();
+        let mut target = World::default();
+        let mut tick = |server: &mut TestServer, source: &World, target: &mut World| {
+            update(server, &mut replicator, source);
+            testing::pump(server, &mut [&mut client]);
+            let resources = client.resources();
+            let resource = resources.inbound("entities".into()).unwrap();
+            mirror.update(target, resource);
+        };
+
+        let player = source.insert(Some(Position(1.0, 2.0)), Some("player"));
+        let crate_ = source.insert(Some(Position(5.0, 5.0)), None);
+        tick(&mut server, &source, &mut target);
+        assert_eq!(target.components(), source.components());
+
+        source.entities.get_mut(&player).unwrap().0 = Some(Position(1.5, 2.0));
+        source.entities.get_mut(&player).unwrap().1 = None;
+        source.despawn(crate_);
+        source.insert(None, Some("flag"));
+        tick(&mut server, &source, &mut target);
+        assert_eq!(target.components(), source.components());
+        assert_eq!(target.entities.len(), 2);
+    }
+
+    #[test]
+    fn changed_components_are_sent_as_xor_deltas() {
+        let id = NetworkId(1);
+        let name = "a long name that barely changes".to_string();
+        let mut old = Entities::default();
+        let components = old.entities.entry(id).or_default();
+        components.insert(0, name.clone().into_bytes());
+        let mut new = old.clone();
+        new.entities.get_mut(&id).unwrap().get_mut(&0).unwrap()[2] = b'L';
+
+        let mut strategy = EntityDelta::default();
+        let mut buffer = Vec::new();
+        assert_eq!(strategy.diff(&old, &new, &mut buffer), Some(true));
+        assert!(buffer.len() < name.len() / 2, "{:?}", buffer);
+        let mut target = old.clone();
+        strategy.apply(&buffer, &mut target).unwrap();
+        assert_eq!(target, new);
+        buffer.clear();
+        assert_eq!(strategy.diff(&new, &new, &mut buffer), Some(false));
+    }
+
+    #[test]
+    fn encodings_reuse_the_buffers_of_the_previous_scan() {
+        let mut server = testing::server();
+        let mut replicator = replicator(&mut server);
+        let mut world = World::default();
+        let entity = world.insert(Some(Position(0.0, 0.0)), None);
+        let pointer = |server: &mut TestServer| {
+            let entities = entities(server);
+            entities.entities[&NetworkId(1)][&1].as_ptr()
+        };
+
+        update(&mut server, &mut replicator, &world);
+        let first = pointer(&mut server);
+        world.entities.get_mut(&entity).unwrap().0 = Some(Position(1.0, 0.0));
+        update(&mut server, &mut replicator, &world);
+        assert_ne!(pointer(&mut server), first);
+        world.entities.get_mut(&entity).unwrap().0 = Some(Position(2.0, 0.0));
+        update(&mut server, &mut replicator, &world);
+        assert_eq!(pointer(&mut server), first);
+    }
+}
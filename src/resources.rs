@@ -1,57 +1,122 @@
+use crate::codec::{Codec, DefaultCodec};
+use crate::diff::{DiffStrategy, SerdeDiffStrategy};
+use crate::handshake::Schema;
+use crate::interpolation::Interpolate;
+use crate::wire::{read_varint, write_varint, DIFF, FULL};
 use downcast_rs::{impl_downcast, Downcast};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_diff::{Apply, Diff, SerdeDiff};
-use std::collections::HashMap;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_diff::SerdeDiff;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::ops::{Deref, DerefMut};
 
-pub struct HashMapResources<I, O>
+pub struct HashMapResources<I, O, K = DefaultCodec>
 where
     I: Eq + Hash,
     O: Eq + Hash,
+    K: Codec,
 {
-    pub(crate) inbound_resources: HashMap<I, Box<dyn InternalInboundResource>>,
-    pub(crate) outbound_resources: HashMap<O, Box<dyn InternalOutboundResource>>,
+    pub(crate) inbound_resources: HashMap<I, Box<dyn InternalInboundResource<K>>>,
+    pub(crate) outbound_resources: HashMap<O, Box<dyn InternalOutboundResource<K>>>,
+    pub(crate) schema: Schema,
 }
 
-impl<I, O> Default for HashMapResources<I, O>
+impl<I, O, K> Default for HashMapResources<I, O, K>
 where
     I: Eq + Hash,
     O: Eq + Hash,
+    K: Codec,
 {
     fn default() -> Self {
         Self {
             inbound_resources: HashMap::new(),
             outbound_resources: HashMap::new(),
+            schema: Schema::default(),
         }
     }
 }
 
-impl<I, O> Resources<I, O> for HashMapResources<I, O>
+impl<I, O, K> HashMapResources<I, O, K>
 where
     I: Eq + Hash,
     O: Eq + Hash,
+    K: Codec,
+{
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn schema_mut(&mut self) -> &mut Schema {
+        &mut self.schema
+    }
+}
+
+impl<I, O, K> Resources<I, O> for HashMapResources<I, O, K>
+where
+    I: Eq + Hash + AsRef<str>,
+    O: Eq + Hash + AsRef<str>,
+    K: Codec,
 {
     fn register_inbound<T>(&mut self, identifier: I, resource: T)
     where
-        T: 'static + Debug + DeserializeOwned + SerdeDiff + Send,
+        T: 'static + Debug + Clone + DeserializeOwned + SerdeDiff + Send,
     {
-        self.inbound_resources
-            .insert(identifier, Box::new(InboundResource::new(resource)));
+        let strategy = SerdeDiffStrategy::<K>::with_codec();
+        self.register_inbound_with(identifier, resource, strategy);
     }
 
     fn register_outbound<T>(&mut self, identifier: O, resource: T)
     where
         T: 'static + Debug + Clone + Serialize + SerdeDiff + Send,
     {
+        let strategy = SerdeDiffStrategy::<K>::with_codec();
+        self.register_outbound_with(identifier, resource, strategy);
+    }
+
+    fn register_inbound_with<T, D>(&mut self, identifier: I, resource: T, strategy: D)
+    where
+        T: 'static + Debug + Clone + DeserializeOwned + Send,
+        D: DiffStrategy<T>,
+    {
+        self.schema.declare::<T>(identifier.as_ref());
+        let resource = InboundResource::new(resource, Box::new(strategy));
+        self.inbound_resources
+            .insert(identifier, Box::new(resource));
+    }
+
+    fn register_outbound_with<T, D>(&mut self, identifier: O, resource: T, strategy: D)
+    where
+        T: 'static + Debug + Clone + Serialize + Send,
+        D: DiffStrategy<T>,
+    {
+        self.schema.declare::<T>(identifier.as_ref());
+        let resource = OutboundResource::new(resource, Box::new(strategy));
         self.outbound_resources
-            .insert(identifier, Box::new(OutboundResource::new(resource)));
+            .insert(identifier, Box::new(resource));
+    }
+
+    fn register_networked_inbound<T: NetworkedResource>(&mut self, identifier: I, resource: T) {
+        self.register_inbound_with(identifier, resource, T::strategy::<K>());
+    }
+
+    fn register_networked_outbound<T: NetworkedResource>(&mut self, identifier: O, resource: T) {
+        self.schema.declare::<T>(identifier.as_ref());
+        let mut resource = OutboundResource::new(resource, T::strategy::<K>());
+        if T::FIELDS
+            .iter()
+            .any(|f| f.visibility != Visibility::Everyone)
+        {
+            resource.set_projection(Some(T::project));
+        }
+        self.outbound_resources
+            .insert(identifier, Box::new(resource));
     }
 
     fn inbound<T>(&self, identifier: I) -> Option<&InboundResource<T>>
     where
-        T: 'static + Debug + DeserializeOwned + SerdeDiff + Send,
+        T: 'static + Debug + Clone + DeserializeOwned + Send,
     {
         self.inbound_resources
             .get(&identifier)
@@ -60,7 +125,7 @@ where
 
     fn outbound<T>(&self, identifier: O) -> Option<&OutboundResource<T>>
     where
-        T: 'static + Debug + Clone + Serialize + SerdeDiff + Send,
+        T: 'static + Debug + Clone + Serialize + Send,
     {
         self.outbound_resources
             .get(&identifier)
@@ -69,7 +134,7 @@ where
 
     fn outbound_mut<T>(&mut self, identifier: O) -> Option<&mut OutboundResource<T>>
     where
-        T: 'static + Debug + Clone + Serialize + SerdeDiff + Send,
+        T: 'static + Debug + Clone + Serialize + Send,
     {
         self.outbound_resources
             .get_mut(&identifier)
@@ -80,44 +145,170 @@ where
 pub trait Resources<I, O>: Default {
     fn register_inbound<T>(&mut self, identifier: I, resource: T)
     where
-        T: 'static + Debug + DeserializeOwned + SerdeDiff + Send;
+        T: 'static + Debug + Clone + DeserializeOwned + SerdeDiff + Send;
 
     fn register_outbound<T>(&mut self, identifier: O, resource: T)
     where
         T: 'static + Debug + Clone + Serialize + SerdeDiff + Send;
 
+    fn register_inbound_with<T, D>(&mut self, identifier: I, resource: T, strategy: D)
+    where
+        T: 'static + Debug + Clone + DeserializeOwned + Send,
+        D: DiffStrategy<T>;
+
+    fn register_outbound_with<T, D>(&mut self, identifier: O, resource: T, strategy: D)
+    where
+        T: 'static + Debug + Clone + Serialize + Send,
+        D: DiffStrategy<T>;
+
+    fn register_networked_inbound<T: NetworkedResource>(&mut self, identifier: I, resource: T);
+
+    fn register_networked_outbound<T: NetworkedResource>(&mut self, identifier: O, resource: T);
+
     fn inbound<T>(&self, identifier: I) -> Option<&InboundResource<T>>
     where
-        T: 'static + Debug + DeserializeOwned + SerdeDiff + Send;
+        T: 'static + Debug + Clone + DeserializeOwned + Send;
 
     fn outbound<T>(&self, identifier: O) -> Option<&OutboundResource<T>>
     where
-        T: 'static + Debug + Clone + Serialize + SerdeDiff + Send;
+        T: 'static + Debug + Clone + Serialize + Send;
 
     fn outbound_mut<T>(&mut self, identifier: O) -> Option<&mut OutboundResource<T>>
     where
-        T: 'static + Debug + Clone + Serialize + SerdeDiff + Send;
+        T: 'static + Debug + Clone + Serialize + Send;
+}
+
+// Implemented by `#[derive(Networked)]`, which also generates the serde impls. Types with a
+// `#[networked(changes)]` field are diffed field by field, others with `XorDelta`.
+pub trait NetworkedResource: 'static + Debug + Clone + Serialize + DeserializeOwned + Send {
+    const FIELDS: &'static [Field];
+
+    fn strategy<K: Codec>() -> Box<dyn DiffStrategy<Self>>;
+
+    // Resets the fields the recipient isn't allowed to see to their default value.
+    fn project(&mut self, recipient: Recipient);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub visibility: Visibility,
+    pub interpolate: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Visibility {
+    Everyone,
+    Owner,
+    Server,
+}
+
+impl Visibility {
+    pub fn is_visible_to(self, recipient: Recipient) -> bool {
+        match self {
+            Visibility::Everyone => true,
+            Visibility::Owner => recipient != Recipient::Others,
+            Visibility::Server => recipient == Recipient::Server,
+        }
+    }
+}
+
+// Who an outbound resource is serialized for. Clients always send to the server, the server
+// sends to the resource's owner or to everyone else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Recipient {
+    Server,
+    Owner,
+    Others,
+}
+
+// Past snapshots kept so diffs can be taken against, or applied to, an older baseline.
+const WINDOW: usize = 16;
+
+// Why a received update wasn't applied. Stale updates are expected on unordered transports,
+// the others leave the sender diffing against a state the receiver doesn't have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Discarded {
+    Stale,
+    Invalid,
+    Rejected,
 }
 
 pub struct InboundResource<T>
 where
-    T: Debug + DeserializeOwned + SerdeDiff,
+    T: Debug + DeserializeOwned,
 {
+    history: VecDeque<(usize, T)>,
+    strategy: Box<dyn DiffStrategy<T>>,
     data: T,
 }
 
 impl<T> InboundResource<T>
 where
-    T: Debug + DeserializeOwned + SerdeDiff,
+    T: Debug + DeserializeOwned,
 {
-    pub(crate) fn new(data: T) -> InboundResource<T> {
-        Self { data }
+    pub(crate) fn new(data: T, strategy: Box<dyn DiffStrategy<T>>) -> InboundResource<T> {
+        Self {
+            history: VecDeque::new(),
+            strategy,
+            data,
+        }
+    }
+
+    pub fn generation(&self) -> Option<usize> {
+        self.history.back().map(|(generation, _)| *generation)
+    }
+
+    // Blends the two most recent states, or returns the latest one if there is only one.
+    pub fn interpolate(&self, t: f32) -> T
+    where
+        T: Interpolate + Clone,
+    {
+        let mut history = self.history.iter().rev();
+        match (history.next(), history.next()) {
+            (Some((_, latest)), Some((_, previous))) => previous.interpolate(latest, t),
+            _ => self.data.clone(),
+        }
+    }
+}
+
+impl<T> InboundResource<T>
+where
+    T: 'static + Debug + Clone + DeserializeOwned,
+{
+    pub(crate) fn decode<K: Codec>(&mut self, data: &[u8]) -> Result<(usize, T), Discarded> {
+        let (tag, mut data) = data.split_first().ok_or(Discarded::Invalid)?;
+        let generation = read_varint(&mut data).ok_or(Discarded::Invalid)? as usize;
+        if self.generation() >= Some(generation) {
+            return Err(Discarded::Stale);
+        }
+        let decoded = match *tag {
+            DIFF => read_varint(&mut data).and_then(|baseline| {
+                let (_, baseline) = self.history.iter().find(|(g, _)| *g == baseline as usize)?;
+                let mut decoded = baseline.clone();
+                self.strategy.apply(data, &mut decoded)?;
+                Some(decoded)
+            }),
+            FULL => K::deserialize::<T>(data),
+            _ => None,
+        };
+        decoded
+            .map(|decoded| (generation, decoded))
+            .ok_or(Discarded::Invalid)
+    }
+
+    pub(crate) fn set(&mut self, generation: usize, data: T) {
+        if self.history.len() == WINDOW {
+            self.history.pop_front();
+        }
+        self.history.push_back((generation, data.clone()));
+        self.data = data;
     }
 }
 
 impl<T> Deref for InboundResource<T>
 where
-    T: Debug + DeserializeOwned + SerdeDiff,
+    T: Debug + DeserializeOwned,
 {
     type Target = T;
 
@@ -128,7 +319,7 @@ where
 
 pub(crate) struct Snapshot<T>
 where
-    T: Debug + Clone + Serialize + SerdeDiff,
+    T: Debug + Clone + Serialize,
 {
     data: T,
     generation: usize,
@@ -136,7 +327,7 @@ where
 
 impl<T> Snapshot<T>
 where
-    T: Debug + Clone + Serialize + SerdeDiff,
+    T: Debug + Clone + Serialize,
 {
     fn new(data: T, generation: usize) -> Self {
         Self { data, generation }
@@ -145,29 +336,40 @@ where
 
 pub struct OutboundResource<T>
 where
-    T: Debug + Clone + Serialize + SerdeDiff,
+    T: Debug + Clone + Serialize,
 {
-    snapshot: Option<Snapshot<T>>,
+    snapshots: VecDeque<Snapshot<T>>,
+    strategy: Box<dyn DiffStrategy<T>>,
+    projection: Option<fn(&mut T, Recipient)>,
     dirty: bool,
+    scratch: Vec<u8>,
     data: T,
 }
 
 impl<T> OutboundResource<T>
 where
-    T: Debug + Clone + Serialize + SerdeDiff,
+    T: Debug + Clone + Serialize,
 {
-    pub(crate) fn new(data: T) -> OutboundResource<T> {
+    pub(crate) fn new(data: T, strategy: Box<dyn DiffStrategy<T>>) -> OutboundResource<T> {
         Self {
-            snapshot: None,
+            snapshots: VecDeque::new(),
+            strategy,
+            projection: None,
             dirty: false,
+            scratch: Vec::new(),
             data,
         }
     }
+
+    // Every recipient gets the full resource unless a projection is set.
+    pub fn set_projection(&mut self, projection: Option<fn(&mut T, Recipient)>) {
+        self.projection = projection;
+    }
 }
 
 impl<T> Deref for OutboundResource<T>
 where
-    T: Debug + Clone + Serialize + SerdeDiff,
+    T: Debug + Clone + Serialize,
 {
     type Target = T;
 
@@ -178,7 +380,7 @@ where
 
 impl<T> DerefMut for OutboundResource<T>
 where
-    T: Debug + Clone + Serialize + SerdeDiff,
+    T: Debug + Clone + Serialize,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
         self.dirty = true;
@@ -186,27 +388,37 @@ where
     }
 }
 
-pub(crate) trait InternalInboundResource: Downcast + Send {
-    fn deserialize(&mut self, data: &Vec<u8>);
+pub(crate) trait InternalInboundResource<K: Codec>: Downcast + Send {
+    fn deserialize(&mut self, data: &[u8]) -> Result<usize, Discarded>;
+    fn reset(&mut self);
 }
 
-pub(crate) trait InternalOutboundResource: Downcast + Send {
+pub(crate) trait InternalOutboundResource<K: Codec>: Downcast + Send {
     fn is_dirty(&self) -> bool;
     fn set_dirty(&mut self, dirty: bool);
     fn snapshot(&mut self);
     fn generation(&self) -> Option<usize>;
-    fn serialize(&self, full: bool) -> Option<Vec<u8>>;
+    fn serialize(
+        &mut self,
+        baseline: Option<usize>,
+        recipient: Recipient,
+        buffer: &mut Vec<u8>,
+    ) -> bool;
 }
 
-impl_downcast!(InternalInboundResource);
-impl_downcast!(InternalOutboundResource);
+impl_downcast!(InternalInboundResource<K> where K: Codec);
+impl_downcast!(InternalOutboundResource<K> where K: Codec);
 
-impl<T: 'static> InternalOutboundResource for OutboundResource<T>
+impl<T: 'static, K> InternalOutboundResource<K> for OutboundResource<T>
 where
-    T: Debug + Clone + Serialize + SerdeDiff + Send,
+    T: Debug + Clone + Serialize + Send,
+    K: Codec,
 {
     fn is_dirty(&self) -> bool {
-        self.dirty
+        match self.snapshots.back() {
+            Some(latest) => self.dirty && self.strategy.is_changed(&latest.data, &self.data),
+            None => self.dirty,
+        }
     }
 
     fn set_dirty(&mut self, dirty: bool) {
@@ -214,56 +426,176 @@ where
     }
 
     fn snapshot(&mut self) {
-        let generation = self.snapshot.as_ref().map_or(0, |s| s.generation + 1);
-        self.snapshot = Some(Snapshot::new(self.data.clone(), generation));
+        let generation = self.snapshots.back().map_or(0, |s| s.generation + 1);
+        if self.snapshots.len() == WINDOW {
+            self.snapshots.pop_front();
+        }
+        self.snapshots
+            .push_back(Snapshot::new(self.data.clone(), generation));
     }
 
     fn generation(&self) -> Option<usize> {
-        self.snapshot.as_ref().map(|s| s.generation)
-    }
-
-    fn serialize(&self, full: bool) -> Option<Vec<u8>> {
-        if full || self.snapshot.is_none() {
-            let data = rmp_serde::to_vec(&self.data).unwrap();
-            Some(rmp_serde::to_vec(&SerializedResource::Full(data)).unwrap())
-        } else {
-            let snapshot = self.snapshot.as_ref().unwrap();
-            let diff = Diff::serializable(&snapshot.data, &self.data);
-            let diff_serialized = rmp_serde::to_vec(&diff).unwrap();
-            if diff.has_changes() {
-                let data = rmp_serde::to_vec(&self.data).unwrap();
-                if data.len() > diff_serialized.len() {
-                    Some(rmp_serde::to_vec(&SerializedResource::Diff(diff_serialized)).unwrap())
-                } else {
-                    Some(rmp_serde::to_vec(&SerializedResource::Full(data)).unwrap())
+        self.snapshots.back().map(|s| s.generation)
+    }
+
+    fn serialize(
+        &mut self,
+        baseline: Option<usize>,
+        recipient: Recipient,
+        buffer: &mut Vec<u8>,
+    ) -> bool {
+        let start = buffer.len();
+        let generation = self.snapshots.back().map_or(0, |s| s.generation + 1);
+        let snapshots = &self.snapshots;
+        let baseline = baseline.and_then(|b| snapshots.iter().find(|s| s.generation == b));
+        // Projections are deterministic, so a projected baseline matches what the recipient got.
+        let data = project(&self.data, self.projection, recipient);
+        if let Some(snapshot) = baseline {
+            let old = project(&snapshot.data, self.projection, recipient);
+            buffer.push(DIFF);
+            write_varint(generation as u32, buffer);
+            write_varint(snapshot.generation as u32, buffer);
+            match self.strategy.diff(&old, &data, buffer) {
+                Some(true) => {
+                    self.scratch.clear();
+                    K::serialize_into(&*data, &mut self.scratch).unwrap();
+                    if self.scratch.len() >= buffer.len() - start {
+                        return true;
+                    }
                 }
-            } else {
-                None
+                Some(false) => {
+                    buffer.truncate(start);
+                    return false;
+                }
+                None => {}
             }
+            buffer.truncate(start);
         }
+        buffer.push(FULL);
+        write_varint(generation as u32, buffer);
+        K::serialize_into(&*data, buffer).unwrap();
+        true
     }
 }
 
-impl<T: 'static> InternalInboundResource for InboundResource<T>
+fn project<T: Clone>(
+    data: &T,
+    projection: Option<fn(&mut T, Recipient)>,
+    recipient: Recipient,
+) -> Cow<'_, T> {
+    match projection {
+        Some(projection) => {
+            let mut projected = data.clone();
+            projection(&mut projected, recipient);
+            Cow::Owned(projected)
+        }
+        None => Cow::Borrowed(data),
+    }
+}
+
+impl<T: 'static, K> InternalInboundResource<K> for InboundResource<T>
 where
-    T: Debug + DeserializeOwned + SerdeDiff + Send,
+    T: Debug + Clone + DeserializeOwned + Send,
+    K: Codec,
 {
-    fn deserialize(&mut self, data: &Vec<u8>) {
-        let data = rmp_serde::from_slice::<SerializedResource>(data.as_slice()).unwrap();
-        match data {
-            SerializedResource::Diff(diff) => {
-                let mut diff = rmp_serde::Deserializer::new(diff.as_slice());
-                Apply::apply(&mut diff, &mut self.data).unwrap();
-            }
-            SerializedResource::Full(full) => {
-                self.data = rmp_serde::from_slice::<T>(&full).unwrap()
-            }
-        }
+    fn deserialize(&mut self, data: &[u8]) -> Result<usize, Discarded> {
+        let (generation, data) = self.decode::<K>(data)?;
+        self.set(generation, data);
+        Ok(generation)
+    }
+
+    fn reset(&mut self) {
+        self.history.clear();
     }
 }
 
-#[derive(Serialize, Deserialize)]
-enum SerializedResource {
-    Diff(Vec<u8>),
-    Full(Vec<u8>),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::FullOnly;
+    use crate::server::OutboundIdentifier;
+    use crate::testing::{self, TestClient, TestServer};
+    use crate::transport::ConnectionId;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    struct Player {
+        name: String,
+        ammunition: u32,
+        cheats: bool,
+    }
+
+    fn project(player: &mut Player, recipient: Recipient) {
+        if !Visibility::Owner.is_visible_to(recipient) {
+            player.ammunition = 0;
+        }
+        if !Visibility::Server.is_visible_to(recipient) {
+            player.cheats = false;
+        }
+    }
+
+    fn player_client(server: &mut TestServer) -> TestClient {
+        let mut client = testing::client(server);
+        let resources = client.resources_mut();
+        resources.register_inbound_with("player".into(), Player::default(), FullOnly);
+        testing::connect(server, &mut client);
+        client
+    }
+
+    fn received(client: &TestClient) -> (u32, bool) {
+        let player = client
+            .resources()
+            .inbound::<Player>("player".into())
+            .unwrap();
+        (player.ammunition, player.cheats)
+    }
+
+    fn rename(server: &mut TestServer, name: &str) {
+        let resources = server.resources_mut();
+        let player = resources.outbound_mut::<Player>("player".into()).unwrap();
+        player.name = name.into();
+    }
+
+    #[test]
+    fn visibilities_match_their_recipients() {
+        let visible = |visibility: Visibility| {
+            let recipients = [Recipient::Server, Recipient::Owner, Recipient::Others];
+            recipients
+                .iter()
+                .map(|r| visibility.is_visible_to(*r))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(visible(Visibility::Everyone), vec![true, true, true]);
+        assert_eq!(visible(Visibility::Owner), vec![true, true, false]);
+        assert_eq!(visible(Visibility::Server), vec![true, false, false]);
+    }
+
+    #[test]
+    fn owner_only_fields_reach_the_owner() {
+        let mut server = testing::server();
+        let player = Player {
+            name: "player".into(),
+            ammunition: 30,
+            cheats: true,
+        };
+        let resources = server.resources_mut();
+        resources.register_outbound_with("player".into(), player, FullOnly);
+        let resource = resources.outbound_mut::<Player>("player".into()).unwrap();
+        resource.set_projection(Some(project));
+        let mut first = player_client(&mut server);
+        let mut second = player_client(&mut server);
+        let identifier = OutboundIdentifier::from("player");
+        server.set_owner(identifier.clone(), Some(ConnectionId(0)));
+
+        rename(&mut server, "first");
+        testing::pump(&mut server, &mut [&mut first, &mut second]);
+        assert_eq!(received(&first), (30, false));
+        assert_eq!(received(&second), (0, false));
+
+        server.set_owner(identifier, Some(ConnectionId(1)));
+        rename(&mut server, "second");
+        testing::pump(&mut server, &mut [&mut first, &mut second]);
+        assert_eq!(received(&first), (0, false));
+        assert_eq!(received(&second), (30, false));
+    }
 }
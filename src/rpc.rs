@@ -0,0 +1,284 @@
+use crate::codec::Codec;
+use instant::Instant;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallError {
+    Timeout,
+    Disconnected,
+    // The response couldn't be decoded as the expected type.
+    Decode,
+    // No call was registered for the request and response types.
+    Unregistered,
+}
+
+struct CallState<T> {
+    result: Option<Result<T, CallError>>,
+    waker: Option<Waker>,
+}
+
+pub struct Call<T> {
+    state: Arc<Mutex<CallState<T>>>,
+}
+
+impl<T> Call<T> {
+    pub(crate) fn failed(error: CallError) -> Self {
+        let state = CallState {
+            result: Some(Err(error)),
+            waker: None,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn try_result(&self) -> Option<Result<T, CallError>> {
+        self.state.lock().unwrap().result.take()
+    }
+}
+
+impl<T> Future for Call<T> {
+    type Output = Result<T, CallError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub(crate) trait InternalPendingCall<K: Codec>: Send {
+    fn deadline(&self) -> Instant;
+    fn resolve(&self, data: &[u8]);
+    fn fail(&self, error: CallError);
+}
+
+struct PendingCall<T> {
+    state: Arc<Mutex<CallState<T>>>,
+    deadline: Instant,
+}
+
+impl<T> PendingCall<T> {
+    fn complete(&self, result: Result<T, CallError>) {
+        let mut state = self.state.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T, K> InternalPendingCall<K> for PendingCall<T>
+where
+    T: DeserializeOwned + Send,
+    K: Codec,
+{
+    fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    fn resolve(&self, data: &[u8]) {
+        match K::deserialize::<T>(data) {
+            Some(response) => self.complete(Ok(response)),
+            None => self.complete(Err(CallError::Decode)),
+        }
+    }
+
+    fn fail(&self, error: CallError) {
+        self.complete(Err(error));
+    }
+}
+
+pub(crate) struct Calls<K: Codec> {
+    next_id: u32,
+    identifiers: HashMap<TypeId, (String, Duration)>,
+    pending: HashMap<u32, Box<dyn InternalPendingCall<K>>>,
+}
+
+impl<K: Codec> Default for Calls<K> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            identifiers: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Codec> Calls<K> {
+    pub(crate) fn register<Req, Resp>(&mut self, identifier: String, timeout: Duration)
+    where
+        Req: 'static + Debug + Serialize + DeserializeOwned + Send,
+        Resp: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        self.identifiers
+            .insert(TypeId::of::<(Req, Resp)>(), (identifier, timeout));
+    }
+
+    pub(crate) fn call<Req, Resp>(&mut self) -> Result<(u32, &str, Call<Resp>), CallError>
+    where
+        Req: 'static + Debug + Serialize + DeserializeOwned + Send,
+        Resp: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        let (identifier, timeout) = self
+            .identifiers
+            .get(&TypeId::of::<(Req, Resp)>())
+            .ok_or(CallError::Unregistered)?;
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let state = Arc::new(Mutex::new(CallState {
+            result: None,
+            waker: None,
+        }));
+        self.pending.insert(
+            id,
+            Box::new(PendingCall {
+                state: Arc::clone(&state),
+                deadline: Instant::now() + *timeout,
+            }),
+        );
+        Ok((id, identifier, Call { state }))
+    }
+
+    pub(crate) fn resolve(&mut self, id: u32, data: &[u8]) {
+        if let Some(call) = self.pending.remove(&id) {
+            call.resolve(data);
+        }
+    }
+
+    pub(crate) fn expire(&mut self, now: Instant) {
+        let expired = self
+            .pending
+            .iter()
+            .filter(|(_, call)| call.deadline() <= now)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            if let Some(call) = self.pending.remove(&id) {
+                call.fail(CallError::Timeout);
+            }
+        }
+    }
+
+    pub(crate) fn fail_all(&mut self, error: CallError) {
+        for (_, call) in self.pending.drain() {
+            call.fail(error);
+        }
+    }
+}
+
+pub(crate) trait InternalHandler<C, K: Codec>: Send {
+    fn handle(&self, connection: &C, data: &[u8], buffer: &mut Vec<u8>) -> bool;
+}
+
+pub(crate) struct Handler<Req, Resp, F> {
+    handler: F,
+    _phantom: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<Req, Resp, F> Handler<Req, Resp, F> {
+    pub(crate) fn new(handler: F) -> Self {
+        Self {
+            handler,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<C, K, Req, Resp, F> InternalHandler<C, K> for Handler<Req, Resp, F>
+where
+    K: Codec,
+    Req: DeserializeOwned,
+    Resp: Serialize,
+    F: Fn(&C, Req) -> Resp + Send,
+{
+    fn handle(&self, connection: &C, data: &[u8], buffer: &mut Vec<u8>) -> bool {
+        match K::deserialize::<Req>(data) {
+            Some(request) => {
+                let response = (self.handler)(connection, request);
+                K::serialize_into(&response, buffer).is_some()
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestClient, TestServer};
+    use crate::{PacketKind, ResourcePacket};
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Add(u32, u32);
+
+    fn connect(timeout: Duration) -> (TestServer, TestClient) {
+        let mut server = testing::server();
+        server.register_handler("add".into(), |_, Add(a, b)| a + b);
+        let mut client = testing::client(&mut server);
+        client.register_call::<Add, u32>("add".into(), timeout);
+        testing::connect(&mut server, &mut client);
+        (server, client)
+    }
+
+    #[test]
+    fn call_is_answered() {
+        let (mut server, mut client) = connect(Duration::from_secs(60));
+        let first = client.call::<Add, u32>(Add(1, 2));
+        let second = client.call::<Add, u32>(Add(3, 4));
+        assert_eq!(first.try_result(), None);
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(first.try_result(), Some(Ok(3)));
+        assert_eq!(second.try_result(), Some(Ok(7)));
+    }
+
+    #[test]
+    fn call_times_out() {
+        let (mut server, mut client) = connect(Duration::from_secs(0));
+        let call = client.call::<Add, u32>(Add(1, 2));
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(call.try_result(), Some(Err(CallError::Timeout)));
+    }
+
+    #[test]
+    fn call_fails_on_disconnect() {
+        let (_, mut client) = connect(Duration::from_secs(60));
+        let call = client.call::<Add, u32>(Add(1, 2));
+        client.disconnect();
+        assert_eq!(call.try_result(), Some(Err(CallError::Disconnected)));
+    }
+
+    #[test]
+    fn undecodable_response_fails_the_call() {
+        let (server, mut client) = connect(Duration::from_secs(60));
+        let call = client.call::<Add, u32>(Add(1, 2));
+        let id = server.resources().schema().id("add").unwrap();
+        let mut packet = Vec::new();
+        // No body at all, which no codec decodes as a `u32`.
+        ResourcePacket::write_header(PacketKind::Response(0), id, &mut packet);
+        client.synchronize_inbound(packet);
+        assert_eq!(call.try_result(), Some(Err(CallError::Decode)));
+    }
+
+    #[test]
+    fn unregistered_call_fails() {
+        let (_, mut client) = connect(Duration::from_secs(60));
+        let call = client.call::<Add, String>(Add(1, 2));
+        assert_eq!(call.try_result(), Some(Err(CallError::Unregistered)));
+    }
+}
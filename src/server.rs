@@ -1,82 +1,274 @@
-use crate::resources::HashMapResources;
-use crate::{node::Node, ResourcePacket, SynchronizeOutbound, Target};
+use crate::codec::{Codec, DefaultCodec};
+use crate::compression::{Compression, Compressions};
+use crate::diff::{DiffStrategy, SerdeDiffStrategy};
+use crate::handshake::{type_name_without_path, HandshakeError, HandshakeResponse, Hello};
+use crate::messages::{MessageError, Messages};
+use crate::resources::{Discarded, HashMapResources, InboundResource, InternalInboundResource};
+use crate::resources::{NetworkedResource, Recipient};
+use crate::rpc::{Handler, InternalHandler};
+use crate::validation::{InternalValidator, Validator, Verdict};
+use crate::wire::read_varint;
+use crate::{node::Node, PacketKind, Reliability, ResourcePacket, Target};
+use crate::{SynchronizeInbound, SynchronizeOutbound};
+use serde::de::DeserializeOwned;
+use serde_diff::SerdeDiff;
 use std::{
     collections::HashMap,
+    fmt::Debug,
     hash::Hash,
+    ops::Range,
     ops::{Deref, DerefMut},
+    vec::Drain,
 };
 
 use serde::{Deserialize, Serialize};
 
-pub struct Server<S, C>
+type InboundTemplate<K> = Box<dyn Fn() -> Box<dyn InternalInboundResource<K>> + Send>;
+
+type ServerNode<S, C, K> = Node<
+    S,
+    InboundIdentifier<C>,
+    OutboundIdentifier<C>,
+    HashMapResources<InboundIdentifier<C>, OutboundIdentifier<C>, K>,
+>;
+
+pub struct Server<S, C, K = DefaultCodec>
 where
     S: SynchronizeOutbound<C>,
     C: Eq + Hash + Clone,
+    K: Codec,
 {
+    pub(crate) application_id: String,
     pub(crate) connections: HashMap<C, HashMap<OutboundIdentifier<C>, usize>>,
-    pub(crate) node: Node<
-        S,
-        InboundIdentifier<C>,
-        OutboundIdentifier<C>,
-        HashMapResources<InboundIdentifier<C>, OutboundIdentifier<C>>,
-    >,
+    pub(crate) inbound_templates: HashMap<String, InboundTemplate<K>>,
+    pub(crate) validators: HashMap<String, Box<dyn InternalValidator<C, K>>>,
+    pub(crate) events: Vec<ServerEvent<C>>,
+    pub(crate) messages: Messages<C, K>,
+    pub(crate) handlers: HashMap<String, Box<dyn InternalHandler<C, K>>>,
+    pub(crate) outbound_messages: Vec<(Target<C>, Range<usize>, Reliability)>,
+    pub(crate) message_buffer: Vec<u8>,
+    pub(crate) buffer: Vec<u8>,
+    pub(crate) owners: HashMap<OutboundIdentifier<C>, (Option<C>, usize)>,
+    pub(crate) baselines: Vec<(Option<usize>, Recipient, Range<usize>)>,
+    pub(crate) baseline_buffer: Vec<u8>,
+    pub(crate) compressions: Compressions<C>,
+    pub(crate) node: ServerNode<S, C, K>,
 }
 
-impl<S, C> Deref for Server<S, C>
+impl<S, C, K> Deref for Server<S, C, K>
 where
     S: SynchronizeOutbound<C>,
     C: Eq + Hash + Clone,
+    K: Codec,
 {
-    type Target = Node<
-        S,
-        InboundIdentifier<C>,
-        OutboundIdentifier<C>,
-        HashMapResources<InboundIdentifier<C>, OutboundIdentifier<C>>,
-    >;
+    type Target = ServerNode<S, C, K>;
 
     fn deref(&self) -> &Self::Target {
         &self.node
     }
 }
 
-impl<S, C> DerefMut for Server<S, C>
+impl<S, C, K> DerefMut for Server<S, C, K>
 where
     S: SynchronizeOutbound<C>,
     C: Eq + Hash + Clone,
+    K: Codec,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.node
     }
 }
 
-impl<S, C> Server<S, C>
+impl<S, C, K> Server<S, C, K>
 where
     S: SynchronizeOutbound<C>,
-    C: Eq + Hash + Clone,
+    C: 'static + Eq + Hash + Clone + Send,
+    K: Codec,
 {
     pub fn new(node: S) -> Self {
         Self {
+            application_id: String::new(),
             connections: HashMap::new(),
+            inbound_templates: HashMap::new(),
+            validators: HashMap::new(),
+            events: Vec::new(),
+            messages: Messages::default(),
+            handlers: HashMap::new(),
+            outbound_messages: Vec::new(),
+            message_buffer: Vec::new(),
+            buffer: Vec::new(),
+            owners: HashMap::new(),
+            baselines: Vec::new(),
+            baseline_buffer: Vec::new(),
+            compressions: Compressions::default(),
             node: Node::new(node),
         }
     }
 
+    pub fn application_id(&self) -> &str {
+        &self.application_id
+    }
+
+    pub fn set_application_id(&mut self, application_id: String) {
+        self.application_id = application_id;
+    }
+
     pub fn register_connection(&mut self, connection: C) {
         self.connections.insert(connection, HashMap::new());
     }
 
+    // Resources created for the connection from the templates are dropped with it. Outbound
+    // resources targeting it are kept and skipped until it's registered again.
     pub fn remove_connection(&mut self, connection: C) {
-        self.connections.remove(&connection);
+        let templates = &self.inbound_templates;
+        self.node
+            .resources
+            .inbound_resources
+            .retain(|identifier, _| {
+                identifier.1 != connection || !templates.contains_key(&identifier.0)
+            });
+        self.compressions.connections.remove(&connection);
+        if self.connections.remove(&connection).is_some() {
+            self.events.push(ServerEvent::Disconnected(connection));
+        }
     }
 
     pub fn is_connected(&self, connection: C) -> bool {
         self.connections.contains_key(&connection)
     }
 
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compressions.default = compression;
+    }
+
+    pub fn set_connection_compression(&mut self, connection: C, compression: Option<Compression>) {
+        match compression {
+            Some(compression) => {
+                self.compressions
+                    .connections
+                    .insert(connection, compression);
+            }
+            None => {
+                self.compressions.connections.remove(&connection);
+            }
+        }
+    }
+
+    // Every accepted connection gets its own copy of the resource, identified by its name and
+    // the connection. The name is declared right away so clients are verified against it even
+    // before anyone connected.
+    pub fn register_inbound<T>(&mut self, identifier: String, resource: T)
+    where
+        T: 'static + Debug + Clone + DeserializeOwned + SerdeDiff + Send,
+    {
+        let strategy = SerdeDiffStrategy::<K>::with_codec();
+        self.register_inbound_with(identifier, resource, strategy);
+    }
+
+    pub fn register_inbound_with<T, D>(&mut self, identifier: String, resource: T, strategy: D)
+    where
+        T: 'static + Debug + Clone + DeserializeOwned + Send,
+        D: DiffStrategy<T> + Clone,
+    {
+        let template = move || -> Box<dyn InternalInboundResource<K>> {
+            let strategy = Box::new(strategy.clone());
+            Box::new(InboundResource::new(resource.clone(), strategy))
+        };
+        self.register_inbound_template::<T>(identifier, Box::new(template));
+    }
+
+    pub fn register_networked_inbound<T: NetworkedResource>(
+        &mut self,
+        identifier: String,
+        resource: T,
+    ) {
+        let template = move || -> Box<dyn InternalInboundResource<K>> {
+            Box::new(InboundResource::new(resource.clone(), T::strategy::<K>()))
+        };
+        self.register_inbound_template::<T>(identifier, Box::new(template));
+    }
+
+    fn register_inbound_template<T>(&mut self, identifier: String, template: InboundTemplate<K>) {
+        self.node.resources.schema.declare::<T>(&identifier);
+        let resources = &mut self.node.resources.inbound_resources;
+        for connection in self.connections.keys() {
+            let key = InboundIdentifier(identifier.clone(), connection.clone());
+            resources.insert(key, template());
+        }
+        self.inbound_templates.insert(identifier, template);
+    }
+
+    pub fn register_validator<T, F>(&mut self, identifier: String, validator: F)
+    where
+        T: 'static + Debug + Clone + DeserializeOwned + Send,
+        F: 'static + Fn(&C, &T, &T) -> Verdict<T> + Send,
+    {
+        if let Some(declared) = self.node.resources.schema.type_name(&identifier) {
+            assert_eq!(
+                declared,
+                type_name_without_path::<T>(),
+                "validator for {:?} registered with another type than the resource",
+                identifier
+            );
+        }
+        self.validators
+            .insert(identifier, Box::new(Validator::new(validator)));
+    }
+
+    pub fn remove_validator(&mut self, identifier: String) {
+        self.validators.remove(&identifier);
+    }
+
+    pub fn drain_events(&mut self) -> Drain<'_, ServerEvent<C>> {
+        self.events.drain(..)
+    }
+
+    pub fn register_message<T>(&mut self, identifier: String, reliability: Reliability)
+    where
+        T: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        self.node.resources.schema.declare::<T>(&identifier);
+        self.messages.register::<T>(identifier, reliability);
+    }
+
+    pub fn send_message<T>(&mut self, target: Target<C>, message: T) -> Result<(), MessageError>
+    where
+        T: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        let (identifier, reliability) = self.messages.identifier::<T>()?;
+        let identifier = self.node.resources.schema.id(identifier).unwrap();
+        let buffer = &mut self.message_buffer;
+        let range =
+            ResourcePacket::write::<K, _>(PacketKind::Message, identifier, &message, buffer);
+        self.outbound_messages.push((target, range, reliability));
+        Ok(())
+    }
+
+    pub fn drain_messages<T>(&mut self) -> impl Iterator<Item = (C, T)> + '_
+    where
+        T: 'static + Debug + Serialize + DeserializeOwned + Send,
+    {
+        self.messages.drain::<T>()
+    }
+
+    pub fn register_handler<Req, Resp, F>(&mut self, identifier: String, handler: F)
+    where
+        Req: 'static + Debug + Serialize + DeserializeOwned + Send,
+        Resp: 'static + Debug + Serialize + DeserializeOwned + Send,
+        F: 'static + Fn(&C, Req) -> Resp + Send,
+    {
+        self.node
+            .resources
+            .schema
+            .declare::<(Req, Resp)>(&identifier);
+        self.handlers
+            .insert(identifier, Box::new(Handler::new(handler)));
+    }
+
     pub fn synchronize_outbound_fully(&mut self, identifier: OutboundIdentifier<C>) {
         match &identifier.1 {
             Target::Specific(connection) => {
-                if let Some(generations) = self.connections.get_mut(&connection) {
+                if let Some(generations) = self.connections.get_mut(connection) {
                     generations.remove(&identifier);
                 }
             }
@@ -88,77 +280,310 @@ where
         }
     }
 
+    pub fn owner<'a>(&'a self, identifier: &'a OutboundIdentifier<C>) -> Option<&'a C> {
+        owner(&self.owners, identifier)
+    }
+
+    // The owner receives the owner-only fields of the resource, which defaults to the target of
+    // specific resources. Acknowledgements from before the change refer to the old projections.
+    pub fn set_owner(&mut self, identifier: OutboundIdentifier<C>, owner: Option<C>) {
+        let resources = &self.node.resources.outbound_resources;
+        let latest = resources.get(&identifier).and_then(|r| r.generation());
+        let since = latest.map_or(0, |generation| generation + 1);
+        let previous = self.owner(&identifier).cloned();
+        for connection in previous.iter().chain(owner.iter()) {
+            if let Some(generations) = self.connections.get_mut(connection) {
+                generations.remove(&identifier);
+            }
+        }
+        self.owners.insert(identifier, (owner, since));
+    }
+
     pub fn synchronize_outbound(&mut self) {
+        let owners = &self.owners;
         let resources = &mut self.node.resources.outbound_resources;
+        let schema = &self.node.resources.schema;
         let connections = &mut self.connections;
         let node = &mut self.node.node;
+        let buffer = &mut self.buffer;
+        let baselines = &mut self.baselines;
+        let baseline_buffer = &mut self.baseline_buffer;
+        let compressions = &mut self.compressions;
+
+        for (target, range, reliability) in self.outbound_messages.drain(..) {
+            let payload = &self.message_buffer[range];
+            match target {
+                Target::Specific(connection) => {
+                    if connections.contains_key(&connection) {
+                        let payload = compressions.compress(&connection, payload);
+                        node.synchronize_with(connection, payload, reliability);
+                    }
+                }
+                Target::All => {
+                    for connection in connections.keys() {
+                        let payload = compressions.compress(connection, payload);
+                        node.synchronize_with(connection.clone(), payload, reliability);
+                    }
+                }
+            }
+        }
+        self.message_buffer.clear();
 
         for (identifier, resource) in resources.iter_mut() {
             if resource.is_dirty() {
+                let id = schema.id(&identifier.0).unwrap();
                 match &identifier.1 {
                     Target::Specific(connection) => {
-                        let generations = connections.get_mut(&connection).unwrap();
-                        let generation = generations.get_mut(&identifier);
-                        let packet = ResourcePacket {
-                            identifier: identifier.0.clone(),
-                            data: resource.serialize(generation.is_none()).unwrap(),
+                        // Left dirty so that changes are sent once the connection is registered.
+                        let generations = match connections.get(connection) {
+                            Some(generations) => generations,
+                            None => continue,
                         };
-                        let payload = bincode::serialize(&packet).unwrap();
-                        node.synchronize(connection.clone(), payload.clone());
+                        let baseline = generations.get(identifier).copied();
+                        let recipient = recipient(owners, identifier, connection);
+                        buffer.clear();
+                        ResourcePacket::write_header(PacketKind::Resource, id, buffer);
+                        if resource.serialize(baseline, recipient, buffer) {
+                            let payload = compressions.compress(connection, buffer);
+                            node.synchronize(connection.clone(), payload);
+                        }
                     }
                     Target::All => {
-                        let full_packet = ResourcePacket {
-                            identifier: identifier.0.clone(),
-                            data: resource.serialize(true).unwrap(),
-                        };
-                        let full_payload = bincode::serialize(&full_packet).unwrap();
-                        let diff_packet = ResourcePacket {
-                            identifier: identifier.0.clone(),
-                            data: resource.serialize(false).unwrap(),
-                        };
-                        let diff_payload = bincode::serialize(&diff_packet).unwrap();
-                        for (connection, generations) in connections.iter_mut() {
-                            let generation = generations.get_mut(&identifier);
-                            let payload = if generation.is_none() {
-                                &full_payload
-                            } else {
-                                &diff_payload
+                        // Peers acknowledging the same generation of the same projection share
+                        // one cached payload.
+                        baselines.clear();
+                        baseline_buffer.clear();
+                        for (connection, generations) in connections.iter() {
+                            let baseline = generations.get(identifier).copied();
+                            let recipient = recipient(owners, identifier, connection);
+                            let cached = baselines
+                                .iter()
+                                .find(|(b, r, _)| *b == baseline && *r == recipient);
+                            let range = match cached {
+                                Some((_, _, range)) => range.clone(),
+                                None => {
+                                    let start = baseline_buffer.len();
+                                    let kind = PacketKind::Resource;
+                                    ResourcePacket::write_header(kind, id, baseline_buffer);
+                                    if !resource.serialize(baseline, recipient, baseline_buffer) {
+                                        baseline_buffer.truncate(start);
+                                    }
+                                    let range = start..baseline_buffer.len();
+                                    baselines.push((baseline, recipient, range.clone()));
+                                    range
+                                }
                             };
-                            node.synchronize(connection.clone(), payload.clone());
+                            if !range.is_empty() {
+                                let payload = &baseline_buffer[range];
+                                let payload = compressions.compress(connection, payload);
+                                node.synchronize(connection.clone(), payload);
+                            }
                         }
                     }
                 }
                 resource.snapshot();
-                match &identifier.1 {
-                    Target::Specific(connection) => {
-                        let generations = connections.get_mut(&connection).unwrap();
-                        generations.insert(identifier.clone(), resource.generation().unwrap());
-                    }
-                    Target::All => {
-                        for generations in connections.values_mut() {
-                            generations.insert(identifier.clone(), resource.generation().unwrap());
+                resource.set_dirty(false);
+            }
+        }
+    }
+
+    pub fn receive_inbound(&mut self)
+    where
+        S: SynchronizeInbound<C>,
+    {
+        while let Some((connection, data)) = self.node.node.receive() {
+            self.synchronize_inbound(connection, data);
+        }
+    }
+
+    pub fn synchronize_inbound(&mut self, connection: C, data: Vec<u8>) {
+        let data = match self.compressions.decompress(&connection, data) {
+            Some(data) => data,
+            None => return,
+        };
+        let packet = ResourcePacket::read(&data);
+        if let Some(packet) = packet {
+            if packet.kind == PacketKind::Handshake {
+                self.handshake(connection, packet.data);
+                return;
+            }
+            if !self.connections.contains_key(&connection) {
+                return;
+            }
+            let name = match self.node.resources.schema.identifier(packet.identifier) {
+                Some(name) => name.to_string(),
+                None => return,
+            };
+            match packet.kind {
+                PacketKind::Resource => {}
+                PacketKind::Message => {
+                    self.messages.deserialize(connection, &name, packet.data);
+                    return;
+                }
+                PacketKind::Request(id) => {
+                    if let Some(handler) = self.handlers.get(&name) {
+                        let buffer = &mut self.message_buffer;
+                        let start = buffer.len();
+                        let kind = PacketKind::Response(id);
+                        ResourcePacket::write_header(kind, packet.identifier, buffer);
+                        if handler.handle(&connection, packet.data, buffer) {
+                            self.outbound_messages.push((
+                                Target::Specific(connection),
+                                start..buffer.len(),
+                                Reliability::ReliableUnordered,
+                            ));
+                        } else {
+                            buffer.truncate(start);
                         }
                     }
+                    return;
+                }
+                PacketKind::Ack => {
+                    self.acknowledge(connection, name, packet.data);
+                    return;
+                }
+                PacketKind::Response(_) | PacketKind::Handshake | PacketKind::Nack => return,
+            }
+            // The name may belong to a message, or to a resource this peer doesn't own.
+            let identifier = InboundIdentifier(name, connection);
+            let resource = match self.node.resources.inbound_resources.get_mut(&identifier) {
+                Some(resource) => resource,
+                None => return,
+            };
+            let result = match self.validators.get(&identifier.0) {
+                Some(validator) => {
+                    validator.validate(&identifier.1, resource.as_mut(), packet.data)
+                }
+                None => resource.deserialize(packet.data).map(|_| false),
+            };
+            match result {
+                Ok(false) | Err(Discarded::Stale) => {}
+                Ok(true) => self.nack(identifier.1, packet.identifier),
+                Err(discarded) => {
+                    let connection = identifier.1.clone();
+                    if discarded == Discarded::Rejected {
+                        self.events.push(ServerEvent::Rejected(identifier));
+                    }
+                    self.nack(connection, packet.identifier);
                 }
-                resource.set_dirty(false);
             }
         }
     }
 
-    pub fn synchronize_inbound(&mut self, connection: C, data: Vec<u8>) {
-        let packet = bincode::deserialize::<ResourcePacket>(&data);
-        if let Ok(packet) = packet {
-            let identifier = InboundIdentifier(packet.identifier, connection);
-            self.node
-                .resources_mut()
-                .inbound_resources
-                .get_mut(&identifier)
-                .unwrap()
-                .deserialize(&packet.data);
+    // The client diffs against its latest state, which the server doesn't have when an update
+    // was rejected or lost, so it is asked to send the resource in full.
+    fn nack(&mut self, connection: C, id: u32) {
+        let buffer = &mut self.message_buffer;
+        let start = buffer.len();
+        ResourcePacket::write_header(PacketKind::Nack, id, buffer);
+        self.outbound_messages.push((
+            Target::Specific(connection),
+            start..buffer.len(),
+            Reliability::ReliableUnordered,
+        ));
+    }
+
+    fn handshake(&mut self, connection: C, data: &[u8]) {
+        let hello = match K::deserialize::<Hello>(data) {
+            Some(hello) => hello,
+            None => return,
+        };
+        let response = match hello.verify(&self.application_id, self.node.resources.schema()) {
+            Ok(()) => {
+                if !self.connections.contains_key(&connection) {
+                    self.events.push(ServerEvent::Connected(connection.clone()));
+                }
+                self.register_connection(connection.clone());
+                let resources = &mut self.node.resources.inbound_resources;
+                for (name, template) in self.inbound_templates.iter() {
+                    let identifier = InboundIdentifier(name.clone(), connection.clone());
+                    resources.entry(identifier).or_insert_with(template);
+                }
+                for (identifier, resource) in resources.iter_mut() {
+                    if identifier.1 == connection {
+                        resource.reset();
+                    }
+                }
+                HandshakeResponse::Accepted(self.node.resources.schema.ids().identifiers().to_vec())
+            }
+            Err(error) => {
+                self.events.push(ServerEvent::HandshakeRejected(
+                    connection.clone(),
+                    error.clone(),
+                ));
+                self.remove_connection(connection.clone());
+                HandshakeResponse::Rejected(error)
+            }
+        };
+        let mut payload = Vec::new();
+        ResourcePacket::write::<K, _>(PacketKind::Handshake, 0, &response, &mut payload);
+        self.node
+            .node
+            .synchronize_with(connection, &payload, Reliability::ReliableUnordered);
+    }
+
+    fn acknowledge(&mut self, connection: C, name: String, mut data: &[u8]) {
+        let generation = match read_varint(&mut data) {
+            Some(generation) => generation as usize,
+            None => return,
+        };
+        let resources = &self.node.resources.outbound_resources;
+        let mut identifier = OutboundIdentifier(name, Target::Specific(connection.clone()));
+        if !resources.contains_key(&identifier) {
+            identifier.1 = Target::All;
+        }
+        let latest = resources.get(&identifier).and_then(|r| r.generation());
+        if latest < Some(generation) {
+            return;
+        }
+        if let Some((_, since)) = self.owners.get(&identifier) {
+            if generation < *since {
+                return;
+            }
         }
+        if let Some(generations) = self.connections.get_mut(&connection) {
+            match generations.get_mut(&identifier) {
+                Some(current) => *current = generation.max(*current),
+                None => {
+                    generations.insert(identifier, generation);
+                }
+            }
+        }
+    }
+}
+
+fn owner<'a, C: Eq + Hash>(
+    owners: &'a HashMap<OutboundIdentifier<C>, (Option<C>, usize)>,
+    identifier: &'a OutboundIdentifier<C>,
+) -> Option<&'a C> {
+    match owners.get(identifier) {
+        Some((owner, _)) => owner.as_ref(),
+        None => match &identifier.1 {
+            Target::Specific(connection) => Some(connection),
+            Target::All => None,
+        },
+    }
+}
+
+fn recipient<C: Eq + Hash>(
+    owners: &HashMap<OutboundIdentifier<C>, (Option<C>, usize)>,
+    identifier: &OutboundIdentifier<C>,
+    connection: &C,
+) -> Recipient {
+    if owner(owners, identifier) == Some(connection) {
+        Recipient::Owner
+    } else {
+        Recipient::Others
     }
 }
 
+pub enum ServerEvent<C> {
+    Connected(C),
+    Disconnected(C),
+    HandshakeRejected(C, HandshakeError),
+    Rejected(InboundIdentifier<C>),
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct InboundIdentifier<I>(pub String, pub I);
 
@@ -176,3 +601,143 @@ impl<I> From<&str> for OutboundIdentifier<I> {
         Self(s.into(), Target::All)
     }
 }
+
+impl<I> AsRef<str> for InboundIdentifier<I> {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<I> AsRef<str> for OutboundIdentifier<I> {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{FullOnly, XorDelta};
+    use crate::resources::Resources;
+    use crate::testing::{self, TestClient, TestServer};
+    use crate::transport::ConnectionId;
+    use crate::wire::{DIFF, FULL};
+
+    fn round(server: &mut TestServer, client: &mut TestClient, count: u64) {
+        **server
+            .resources_mut()
+            .outbound_mut::<u64>("count".into())
+            .unwrap() = count;
+        server
+            .send_message(Target::All, String::from("ping"))
+            .unwrap();
+        client.send_message(String::from("pong")).unwrap();
+        testing::pump(server, &mut [client]);
+    }
+
+    #[test]
+    fn outbound_buffers_are_reused_between_synchronizations() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        resources.register_outbound_with("count".into(), 0u64, FullOnly);
+        server.register_message::<String>("chat".into(), Reliability::ReliableOrdered);
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_inbound_with("count".into(), 0u64, FullOnly);
+        client.register_message::<String>("chat".into(), Reliability::ReliableOrdered);
+        testing::connect(&mut server, &mut client);
+
+        round(&mut server, &mut client, 1);
+        let buffers = |server: &TestServer, client: &TestClient| {
+            let server_buffers = [&server.baseline_buffer, &server.message_buffer];
+            let client_buffers = [&client.buffer, &client.message_buffer];
+            let buffers = server_buffers.iter().chain(client_buffers.iter());
+            buffers.map(|buffer| buffer.as_ptr()).collect::<Vec<_>>()
+        };
+        let before = buffers(&server, &client);
+        round(&mut server, &mut client, 2);
+        assert_eq!(buffers(&server, &client), before);
+        assert!(server.message_buffer.is_empty());
+        assert!(client.message_buffer.is_empty());
+
+        let count = client.resources().inbound::<u64>("count".into()).unwrap();
+        assert_eq!(**count, 2);
+        assert_eq!(client.drain_messages::<String>().count(), 2);
+        assert_eq!(server.drain_messages::<String>().count(), 2);
+    }
+
+    fn state_client(server: &mut TestServer) -> TestClient {
+        let mut client = testing::client(server);
+        let resources = client.resources_mut();
+        resources.register_inbound_with("state".into(), vec![0u8; 64], XorDelta::new());
+        testing::connect(server, &mut client);
+        client
+    }
+
+    fn received(client: &mut TestClient) -> Vec<u8> {
+        client.node.node.receive().unwrap().1
+    }
+
+    #[test]
+    fn peers_at_the_same_baseline_share_a_diff() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        resources.register_outbound_with("state".into(), vec![0u8; 64], XorDelta::new());
+        let mut first = state_client(&mut server);
+        let mut second = state_client(&mut server);
+        fn state(server: &mut TestServer) -> &mut Vec<u8> {
+            let resources = server.resources_mut();
+            resources.outbound_mut::<Vec<u8>>("state".into()).unwrap()
+        }
+
+        state(&mut server)[0] = 1;
+        testing::pump(&mut server, &mut [&mut first, &mut second]);
+        let mut third = state_client(&mut server);
+        // Sends the acknowledgements of the first update.
+        testing::pump(&mut server, &mut [&mut first, &mut second, &mut third]);
+
+        state(&mut server)[1] = 2;
+        server.synchronize_outbound();
+        let mut clients = [first, second, third];
+        let packets = clients.iter_mut().map(received).collect::<Vec<_>>();
+        let tag = |packet: &[u8]| ResourcePacket::read(packet).unwrap().data[0];
+        assert_eq!(packets[0], packets[1]);
+        assert_eq!(tag(&packets[0]), DIFF);
+        assert_eq!(tag(&packets[2]), FULL);
+        assert!(packets[0].len() < packets[2].len());
+
+        for (client, packet) in clients.iter_mut().zip(packets) {
+            client.synchronize_inbound(packet);
+            let state = client
+                .resources()
+                .inbound::<Vec<u8>>("state".into())
+                .unwrap();
+            assert_eq!(state[..3], [1, 2, 0]);
+        }
+    }
+
+    #[test]
+    fn resources_of_disconnected_peers_wait_for_them() {
+        let mut server = testing::server();
+        let connection = ConnectionId(0);
+        let identifier = OutboundIdentifier("count".into(), Target::Specific(connection));
+        let resources = server.resources_mut();
+        resources.register_outbound_with(identifier.clone(), 0u64, FullOnly);
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_inbound_with("count".into(), 0u64, FullOnly);
+        testing::connect(&mut server, &mut client);
+        fn count(client: &TestClient) -> u64 {
+            **client.resources().inbound::<u64>("count".into()).unwrap()
+        }
+
+        server.remove_connection(connection);
+        let resources = server.resources_mut();
+        **resources.outbound_mut::<u64>(identifier).unwrap() = 1;
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(count(&client), 0);
+
+        testing::connect(&mut server, &mut client);
+        assert_eq!(count(&client), 1);
+    }
+}
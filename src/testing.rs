@@ -0,0 +1,39 @@
+use crate::client::Client;
+use crate::codec::{Codec, DefaultCodec};
+use crate::server::Server;
+use crate::transport::{memory, ConnectionId};
+
+pub(crate) type TestServer<K = DefaultCodec> = Server<memory::Listener, ConnectionId, K>;
+
+pub(crate) type TestClient<K = DefaultCodec> = Client<memory::Stream, (), K>;
+
+pub(crate) fn server() -> TestServer {
+    server_with_codec()
+}
+
+pub(crate) fn server_with_codec<K: Codec>() -> TestServer<K> {
+    Server::new(memory::Listener::new())
+}
+
+// The client still has to register its resources and call `connect`.
+pub(crate) fn client<K: Codec>(server: &mut TestServer<K>) -> TestClient<K> {
+    Client::new(server.connect(), ())
+}
+
+pub(crate) fn connect<K: Codec>(server: &mut TestServer<K>, client: &mut TestClient<K>) {
+    client.handshake();
+    pump(server, &mut [client]);
+    assert!(client.is_connected(), "{:?}", client.state());
+}
+
+// One round trip: clients send, the server receives and answers, clients receive.
+pub(crate) fn pump<K: Codec>(server: &mut TestServer<K>, clients: &mut [&mut TestClient<K>]) {
+    for client in clients.iter_mut() {
+        client.synchronize_outbound();
+    }
+    server.poll_connections();
+    server.synchronize_outbound();
+    for client in clients.iter_mut() {
+        client.receive_inbound();
+    }
+}
@@ -0,0 +1,450 @@
+use crate::codec::Codec;
+use crate::server::{Server, ServerEvent};
+use crate::{Reliability, SynchronizeOutbound};
+use futures_core::Stream;
+use std::collections::{HashMap, VecDeque};
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::ReadBuf;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportEvent<C> {
+    Connected(C),
+    Packet(C, Vec<u8>),
+    Disconnected(C),
+}
+
+// Sending stays synchronous, so transports queue packets rather than wait on the socket.
+pub trait Transport<C>: SynchronizeOutbound<C> {
+    // Returns `None` once the transport is closed.
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<TransportEvent<C>>>;
+}
+
+// Packets are queued and sent the next time the transport is polled. Peers are connected by
+// their first packet and only disconnected when a timeout was set.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    queue: VecDeque<(SocketAddr, Vec<u8>)>,
+    buffer: Vec<u8>,
+    last_seen: HashMap<SocketAddr, Instant>,
+    events: VecDeque<TransportEvent<SocketAddr>>,
+    expiry: Option<(Duration, Interval)>,
+}
+
+impl UdpTransport {
+    pub fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            queue: VecDeque::new(),
+            buffer: vec![0; MAX_DATAGRAM_SIZE],
+            last_seen: HashMap::new(),
+            events: VecDeque::new(),
+            expiry: None,
+        }
+    }
+
+    // Disconnects peers once they have been silent for longer than the timeout. Clients don't
+    // send keepalives, so they have to keep sending on their own, for example by acknowledging
+    // a resource that changes every tick.
+    pub fn with_timeout(socket: UdpSocket, timeout: Duration) -> io::Result<Self> {
+        // Peers are checked four times per timeout.
+        let period = timeout / 4;
+        if period == Duration::ZERO {
+            let error = "timeout is too short to check peers";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+        }
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Ok(Self {
+            expiry: Some((timeout, interval)),
+            ..Self::new(socket)
+        })
+    }
+
+    pub async fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        Ok(Self::new(UdpSocket::bind(address).await?))
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+impl SynchronizeOutbound<SocketAddr> for UdpTransport {
+    fn synchronize(&mut self, bound: SocketAddr, data: &[u8]) {
+        self.queue.push_back((bound, data.to_vec()));
+    }
+}
+
+impl Transport<SocketAddr> for UdpTransport {
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<TransportEvent<SocketAddr>>> {
+        // Datagrams that fail to send are dropped like any other lost packet.
+        while let Some((address, data)) = self.queue.front() {
+            match self.socket.poll_send_to(cx, data, *address) {
+                Poll::Ready(_) => {
+                    self.queue.pop_front();
+                }
+                Poll::Pending => break,
+            }
+        }
+        if let Some((timeout, interval)) = &mut self.expiry {
+            // Polled until pending so that the next tick wakes the task.
+            while interval.poll_tick(cx).is_ready() {
+                let now = Instant::now();
+                let events = &mut self.events;
+                self.last_seen.retain(|address, seen| {
+                    let alive = now.duration_since(*seen) < *timeout;
+                    if !alive {
+                        events.push_back(TransportEvent::Disconnected(*address));
+                    }
+                    alive
+                });
+            }
+        }
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+        let mut buffer = ReadBuf::new(&mut self.buffer);
+        match self.socket.poll_recv_from(cx, &mut buffer) {
+            Poll::Ready(Ok(address)) => {
+                let data = buffer.filled().to_vec();
+                let packet = TransportEvent::Packet(address, data);
+                if self.last_seen.insert(address, Instant::now()).is_some() {
+                    return Poll::Ready(Some(packet));
+                }
+                self.events.push_back(packet);
+                Poll::Ready(Some(TransportEvent::Connected(address)))
+            }
+            // Errors such as ICMP port unreachable only concern one peer.
+            Poll::Ready(Err(_)) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// Bridges transports that run in their own tasks.
+pub struct ChannelTransport<C> {
+    outgoing: UnboundedSender<(C, Vec<u8>, Reliability)>,
+    incoming: UnboundedReceiver<TransportEvent<C>>,
+}
+
+impl<C> ChannelTransport<C> {
+    pub fn new(
+        outgoing: UnboundedSender<(C, Vec<u8>, Reliability)>,
+        incoming: UnboundedReceiver<TransportEvent<C>>,
+    ) -> Self {
+        Self { outgoing, incoming }
+    }
+}
+
+impl<C> SynchronizeOutbound<C> for ChannelTransport<C> {
+    fn synchronize(&mut self, bound: C, data: &[u8]) {
+        self.synchronize_with(bound, data, Reliability::ReliableOrdered);
+    }
+
+    fn synchronize_with(&mut self, bound: C, data: &[u8], reliability: Reliability) {
+        let _ = self.outgoing.send((bound, data.to_vec(), reliability));
+    }
+}
+
+impl<C> Transport<C> for ChannelTransport<C> {
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<TransportEvent<C>>> {
+        self.incoming.poll_recv(cx)
+    }
+}
+
+// Lifecycle events forwarded by `Server::run`.
+pub struct ServerEvents<C>(UnboundedReceiver<ServerEvent<C>>);
+
+impl<C> ServerEvents<C> {
+    pub fn channel() -> (UnboundedSender<ServerEvent<C>>, Self) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (sender, Self(receiver))
+    }
+
+    pub async fn next(&mut self) -> Option<ServerEvent<C>> {
+        self.0.recv().await
+    }
+}
+
+impl<C> Stream for ServerEvents<C> {
+    type Item = ServerEvent<C>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+type Command<T> = Box<dyn FnOnce(&mut T) + Send>;
+
+// Runs closures on a server owned by `Server::run`, from any task.
+pub struct ServerHandle<T>(UnboundedSender<Command<T>>);
+
+impl<T> Clone for ServerHandle<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> ServerHandle<T> {
+    pub fn channel() -> (Self, ServerCommands<T>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self(sender), ServerCommands(receiver))
+    }
+
+    // Returns false once the server stopped running. Commands run between ticks, in order.
+    pub fn execute<F>(&self, command: F) -> bool
+    where
+        F: 'static + FnOnce(&mut T) + Send,
+    {
+        self.0.send(Box::new(command)).is_ok()
+    }
+}
+
+pub struct ServerCommands<T>(UnboundedReceiver<Command<T>>);
+
+enum Step<C, T> {
+    Tick,
+    Event(Option<TransportEvent<C>>),
+    Command(Command<T>),
+}
+
+impl<S, C, K> Server<S, C, K>
+where
+    S: Transport<C>,
+    C: 'static + Eq + Hash + Clone + Send,
+    K: Codec,
+{
+    // Handles transport events and commands as they arrive and calls `update` before sending
+    // every tick. Returns once the transport is closed.
+    pub async fn run<F>(
+        &mut self,
+        tick: Duration,
+        events: UnboundedSender<ServerEvent<C>>,
+        mut commands: ServerCommands<Self>,
+        mut update: F,
+    ) where
+        F: FnMut(&mut Self),
+    {
+        let mut interval = time::interval(tick);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut handles = true;
+        loop {
+            let step = poll_fn(|cx| {
+                if interval.poll_tick(cx).is_ready() {
+                    return Poll::Ready(Step::Tick);
+                }
+                if handles {
+                    match commands.0.poll_recv(cx) {
+                        Poll::Ready(Some(command)) => return Poll::Ready(Step::Command(command)),
+                        // Every handle was dropped, which doesn't stop the server.
+                        Poll::Ready(None) => handles = false,
+                        Poll::Pending => {}
+                    }
+                }
+                self.node.node.poll_event(cx).map(Step::Event)
+            })
+            .await;
+            match step {
+                Step::Command(command) => command(self),
+                Step::Tick => {
+                    update(self);
+                    self.synchronize_outbound();
+                }
+                Step::Event(Some(TransportEvent::Packet(connection, data))) => {
+                    self.synchronize_inbound(connection, data);
+                }
+                Step::Event(Some(TransportEvent::Connected(_))) => {}
+                Step::Event(Some(TransportEvent::Disconnected(connection))) => {
+                    self.remove_connection(connection);
+                }
+                Step::Event(None) => return,
+            }
+            for event in self.drain_events() {
+                let _ = events.send(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::diff::FullOnly;
+    use crate::resources::Resources;
+    use crate::server::OutboundIdentifier;
+    use crate::transport::ConnectionId;
+    use crate::SynchronizeInbound;
+    use crate::Target;
+
+    const CONNECTION: ConnectionId = ConnectionId(0);
+
+    // The client end of a `ChannelTransport`.
+    struct Link {
+        outgoing: UnboundedSender<TransportEvent<ConnectionId>>,
+        incoming: UnboundedReceiver<(ConnectionId, Vec<u8>, Reliability)>,
+    }
+
+    impl SynchronizeOutbound<()> for Link {
+        fn synchronize(&mut self, _bound: (), data: &[u8]) {
+            let _ = self
+                .outgoing
+                .send(TransportEvent::Packet(CONNECTION, data.to_vec()));
+        }
+    }
+
+    impl SynchronizeInbound<()> for Link {
+        fn receive(&mut self) -> Option<((), Vec<u8>)> {
+            let (_, data, _) = self.incoming.try_recv().ok()?;
+            Some(((), data))
+        }
+    }
+
+    type LinkedServer = Server<ChannelTransport<ConnectionId>, ConnectionId>;
+
+    fn linked() -> (LinkedServer, Client<Link, ()>) {
+        let (outgoing, incoming) = mpsc::unbounded_channel();
+        let (client_outgoing, client_incoming) = mpsc::unbounded_channel();
+        let server = Server::new(ChannelTransport::new(outgoing, client_incoming));
+        let link = Link {
+            outgoing: client_outgoing,
+            incoming,
+        };
+        (server, Client::new(link, ()))
+    }
+
+    async fn next<T: Transport<SocketAddr>>(transport: &mut T) -> TransportEvent<SocketAddr> {
+        let event = poll_fn(|cx| transport.poll_event(cx));
+        time::timeout(Duration::from_secs(5), event)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn udp_peers_connect_and_time_out() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let timeout = Duration::from_millis(100);
+        let mut transport = UdpTransport::with_timeout(socket, timeout).unwrap();
+        let server = transport.socket().local_addr().unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = peer.local_addr().unwrap();
+
+        peer.send_to(b"first", server).await.unwrap();
+        assert_eq!(
+            next(&mut transport).await,
+            TransportEvent::Connected(address)
+        );
+        let packet = TransportEvent::Packet(address, b"first".to_vec());
+        assert_eq!(next(&mut transport).await, packet);
+        peer.send_to(b"second", server).await.unwrap();
+        let packet = TransportEvent::Packet(address, b"second".to_vec());
+        assert_eq!(next(&mut transport).await, packet);
+
+        transport.synchronize(address, b"reply");
+        let event = next(&mut transport);
+        assert_eq!(event.await, TransportEvent::Disconnected(address));
+        let mut buffer = [0; 16];
+        let (len, _) = peer.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..len], b"reply");
+    }
+
+    #[tokio::test]
+    async fn udp_timeouts_have_to_be_checkable() {
+        for timeout in [Duration::ZERO, Duration::from_nanos(3)] {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let error = UdpTransport::with_timeout(socket, timeout).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        assert!(UdpTransport::with_timeout(socket, Duration::from_nanos(4)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn running_servers_accept_commands() {
+        let (mut server, mut client) = linked();
+        let resources = server.resources_mut();
+        resources.register_outbound_with("count".into(), 0u64, FullOnly);
+        let resources = client.resources_mut();
+        resources.register_inbound_with("count".into(), 0u64, FullOnly);
+        let (sender, mut events) = ServerEvents::channel();
+        let (handle, commands) = ServerHandle::channel();
+
+        let tick = Duration::from_millis(5);
+        let run = server.run(tick, sender, commands, |_| {});
+        let drive = async move {
+            client.handshake();
+            let count = |client: &Client<Link, ()>| {
+                let resources = client.resources();
+                **resources.inbound::<u64>("count".into()).unwrap()
+            };
+            assert!(handle.execute(|server| {
+                let resources = server.resources_mut();
+                **resources.outbound_mut::<u64>("count".into()).unwrap() = 42;
+            }));
+            for _ in 0..200 {
+                time::sleep(tick).await;
+                client.receive_inbound();
+                client.synchronize_outbound();
+                if count(&client) == 42 {
+                    break;
+                }
+            }
+            assert!(client.is_connected());
+            assert_eq!(count(&client), 42);
+            let event = events.next().await;
+            assert!(matches!(event, Some(ServerEvent::Connected(CONNECTION))));
+            // Dropping the client closes the transport, which stops the server.
+            handle
+        };
+        let ((), handle) = tokio::join!(run, drive);
+        assert!(!handle.execute(|_| {}));
+    }
+
+    #[tokio::test]
+    async fn running_servers_keep_resources_of_disconnected_peers() {
+        let (mut server, mut client) = linked();
+        let identifier = OutboundIdentifier("count".into(), Target::Specific(CONNECTION));
+        let resources = server.resources_mut();
+        resources.register_outbound_with(identifier.clone(), 0u64, FullOnly);
+        let resources = client.resources_mut();
+        resources.register_inbound_with("count".into(), 0u64, FullOnly);
+        let (sender, mut events) = ServerEvents::channel();
+        let (handle, commands) = ServerHandle::channel();
+
+        let tick = Duration::from_millis(5);
+        let run = server.run(tick, sender, commands, |_| {});
+        let drive = async move {
+            client.handshake();
+            client.synchronize_outbound();
+            let event = events.next().await;
+            assert!(matches!(event, Some(ServerEvent::Connected(CONNECTION))));
+            let disconnected = TransportEvent::Disconnected(CONNECTION);
+            client.outgoing.send(disconnected).unwrap();
+            let event = events.next().await;
+            assert!(matches!(event, Some(ServerEvent::Disconnected(CONNECTION))));
+            assert!(handle.execute(move |server| {
+                let resources = server.resources_mut();
+                **resources.outbound_mut::<u64>(identifier).unwrap() = 1;
+            }));
+            time::sleep(tick * 4).await;
+            // Closes the transport, so the server has to still be running to return.
+            drop(client);
+        };
+        tokio::join!(run, drive);
+    }
+}
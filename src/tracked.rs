@@ -0,0 +1,305 @@
+use crate::codec::{Codec, DefaultCodec};
+use crate::diff::DiffStrategy;
+use crate::wire::{read_varint, write_varint};
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+// Records the version at which each field was last written. Every write bumps the version, so
+// the fields changed since a snapshot are the ones written after the snapshot's version.
+#[derive(Debug, Clone, Default)]
+pub struct Changes {
+    version: usize,
+    fields: Vec<usize>,
+}
+
+impl Changes {
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    pub fn write(&mut self, field: usize) {
+        if self.fields.len() <= field {
+            self.fields.resize(field + 1, 0);
+        }
+        self.version += 1;
+        self.fields[field] = self.version;
+    }
+
+    pub fn since(&self, version: usize) -> impl Iterator<Item = usize> + '_ {
+        self.fields
+            .iter()
+            .enumerate()
+            .filter(move |(_, written)| **written > version)
+            .map(|(field, _)| field)
+    }
+
+    pub fn is_written_since(&self, field: usize, version: usize) -> bool {
+        self.fields.get(field).copied().unwrap_or(0) > version
+    }
+}
+
+// Types whose fields are written through setters that call `Changes::write` with the field's
+// index. The changes themselves aren't synchronized and should be skipped by serde.
+pub trait Tracked {
+    const FIELD_COUNT: usize;
+
+    fn changes(&self) -> &Changes;
+
+    fn write_field<K: Codec>(&self, field: usize, buffer: &mut Vec<u8>) -> Option<()>;
+
+    fn read_field<K: Codec>(&mut self, field: usize, data: &[u8]) -> Option<()>;
+}
+
+// Sends only the fields written since the baseline, each as its index and encoded length.
+// Fields assigned directly rather than through the setters aren't recorded, so the fields
+// without a recorded write are compared by their encoding instead.
+pub struct TrackedDelta<K = DefaultCodec> {
+    // The baseline's and the new encoding of a field, reused between comparisons.
+    buffers: RefCell<(Vec<u8>, Vec<u8>)>,
+    fields: Vec<usize>,
+    _phantom: PhantomData<fn() -> K>,
+}
+
+impl TrackedDelta {
+    pub fn new() -> Self {
+        Self::with_codec()
+    }
+}
+
+impl<K: Codec> TrackedDelta<K> {
+    pub fn with_codec() -> Self {
+        Self {
+            buffers: RefCell::new((Vec::new(), Vec::new())),
+            fields: Vec::new(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+fn differs<T, K>(
+    old: &T,
+    new: &T,
+    field: usize,
+    old_buffer: &mut Vec<u8>,
+    new_buffer: &mut Vec<u8>,
+) -> bool
+where
+    T: Tracked,
+    K: Codec,
+{
+    if new
+        .changes()
+        .is_written_since(field, old.changes().version())
+    {
+        return true;
+    }
+    old_buffer.clear();
+    new_buffer.clear();
+    let old = old.write_field::<K>(field, old_buffer);
+    let new = new.write_field::<K>(field, new_buffer);
+    old.is_none() || new.is_none() || old_buffer != new_buffer
+}
+
+impl Default for TrackedDelta {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Codec> Clone for TrackedDelta<K> {
+    fn clone(&self) -> Self {
+        Self::with_codec()
+    }
+}
+
+impl<T, K> DiffStrategy<T> for TrackedDelta<K>
+where
+    T: Tracked,
+    K: Codec,
+{
+    fn is_changed(&self, old: &T, new: &T) -> bool {
+        if new.changes().version() != old.changes().version() {
+            return true;
+        }
+        let (old_buffer, new_buffer) = &mut *self.buffers.borrow_mut();
+        (0..T::FIELD_COUNT).any(|field| differs::<T, K>(old, new, field, old_buffer, new_buffer))
+    }
+
+    fn diff(&mut self, old: &T, new: &T, buffer: &mut Vec<u8>) -> Option<bool> {
+        self.fields.clear();
+        let (old_buffer, scratch) = self.buffers.get_mut();
+        for field in 0..T::FIELD_COUNT {
+            if differs::<T, K>(old, new, field, old_buffer, scratch) {
+                self.fields.push(field);
+            }
+        }
+        for field in self.fields.iter().copied() {
+            scratch.clear();
+            new.write_field::<K>(field, scratch)?;
+            write_varint(field as u32, buffer);
+            write_varint(scratch.len() as u32, buffer);
+            buffer.extend_from_slice(scratch);
+        }
+        Some(!self.fields.is_empty())
+    }
+
+    fn apply(&mut self, mut diff: &[u8], target: &mut T) -> Option<()> {
+        while !diff.is_empty() {
+            let field = read_varint(&mut diff)? as usize;
+            let len = read_varint(&mut diff)? as usize;
+            if len > diff.len() {
+                return None;
+            }
+            target.read_field::<K>(field, &diff[..len])?;
+            diff = &diff[len..];
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::DefaultCodec;
+    use crate::resources::Resources;
+    use crate::testing;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    struct Player {
+        health: u32,
+        name: String,
+        #[serde(skip)]
+        changes: Changes,
+    }
+
+    impl Player {
+        fn set_health(&mut self, health: u32) {
+            self.health = health;
+            self.changes.write(0);
+        }
+    }
+
+    impl Tracked for Player {
+        const FIELD_COUNT: usize = 2;
+
+        fn changes(&self) -> &Changes {
+            &self.changes
+        }
+
+        fn write_field<K: Codec>(&self, field: usize, buffer: &mut Vec<u8>) -> Option<()> {
+            match field {
+                0 => K::serialize_into(&self.health, buffer),
+                1 => K::serialize_into(&self.name, buffer),
+                _ => None,
+            }
+        }
+
+        fn read_field<K: Codec>(&mut self, field: usize, data: &[u8]) -> Option<()> {
+            match field {
+                0 => self.health = K::deserialize(data)?,
+                1 => self.name = K::deserialize(data)?,
+                _ => return None,
+            }
+            Some(())
+        }
+    }
+
+    fn diff(old: &Player, new: &Player) -> (bool, Vec<u8>) {
+        let mut strategy = TrackedDelta::new();
+        let mut buffer = Vec::new();
+        let changed = DiffStrategy::is_changed(&strategy, old, new);
+        assert_eq!(strategy.diff(old, new, &mut buffer), Some(changed));
+        (changed, buffer)
+    }
+
+    #[test]
+    fn changes_record_the_last_write_of_each_field() {
+        let mut changes = Changes::default();
+        changes.write(2);
+        let version = changes.version();
+        changes.write(0);
+        changes.write(2);
+        assert_eq!(changes.since(0).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(changes.since(version).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(changes.since(changes.version()).count(), 0);
+    }
+
+    #[test]
+    fn only_written_fields_are_sent() {
+        let old = Player {
+            name: "a very long name that isn't sent again".into(),
+            ..Player::default()
+        };
+        let mut new = old.clone();
+        new.set_health(7);
+        let (changed, buffer) = diff(&old, &new);
+        assert!(changed);
+        assert_eq!(buffer[0], 0);
+        assert!(buffer.len() < 8);
+
+        let mut target = old.clone();
+        TrackedDelta::<DefaultCodec>::new()
+            .apply(&buffer, &mut target)
+            .unwrap();
+        assert_eq!(target.health, 7);
+        assert_eq!(target.name, old.name);
+    }
+
+    #[test]
+    fn direct_writes_are_found_by_comparison() {
+        let old = Player::default();
+        let mut new = old.clone();
+        assert!(!diff(&old, &new).0);
+        new.name = "written directly".into();
+        let (changed, buffer) = diff(&old, &new);
+        assert!(changed);
+        assert_eq!(buffer[0], 1);
+    }
+
+    #[test]
+    fn direct_writes_are_sent_alongside_setter_writes() {
+        let old = Player::default();
+        let mut new = old.clone();
+        new.set_health(7);
+        new.name = "written directly".into();
+        let (changed, buffer) = diff(&old, &new);
+        assert!(changed);
+
+        let mut target = old.clone();
+        TrackedDelta::<DefaultCodec>::new()
+            .apply(&buffer, &mut target)
+            .unwrap();
+        assert_eq!(target.health, 7);
+        assert_eq!(target.name, new.name);
+    }
+
+    #[test]
+    fn untracked_writes_are_replicated() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        resources.register_outbound_with("player".into(), Player::default(), TrackedDelta::new());
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_inbound_with("player".into(), Player::default(), TrackedDelta::new());
+        testing::connect(&mut server, &mut client);
+
+        for round in 0..3 {
+            let resources = server.resources_mut();
+            let player = resources.outbound_mut::<Player>("player".into()).unwrap();
+            player.set_health(round);
+            testing::pump(&mut server, &mut [&mut client]);
+            let resources = server.resources_mut();
+            let player = resources.outbound_mut::<Player>("player".into()).unwrap();
+            player.name = format!("round {}", round);
+            testing::pump(&mut server, &mut [&mut client]);
+
+            let player = client
+                .resources()
+                .inbound::<Player>("player".into())
+                .unwrap();
+            assert_eq!(player.health, round);
+            assert_eq!(player.name, format!("round {}", round));
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use super::{ConnectionId, Listen, ListenerEvent};
+use crate::{SynchronizeInbound, SynchronizeOutbound};
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+
+// The client end of an in-memory connection, such as the one of the player hosting a listen
+// server. Dropping it disconnects it.
+pub struct Stream {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl Stream {
+    pub fn send(&mut self, data: &[u8]) {
+        let _ = self.sender.send(data.to_vec());
+    }
+
+    pub fn recv(&mut self) -> Option<Vec<u8>> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl SynchronizeOutbound<()> for Stream {
+    fn synchronize(&mut self, _bound: (), data: &[u8]) {
+        self.send(data);
+    }
+}
+
+impl SynchronizeInbound<()> for Stream {
+    fn receive(&mut self) -> Option<((), Vec<u8>)> {
+        self.recv().map(|packet| ((), packet))
+    }
+}
+
+type Channel = (Sender<Vec<u8>>, Receiver<Vec<u8>>);
+
+// Hands out in-memory connections to clients running in the same process.
+#[derive(Default)]
+pub struct Listener {
+    connections: HashMap<ConnectionId, Channel>,
+    next_id: u64,
+    events: VecDeque<ListenerEvent>,
+}
+
+impl Listener {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connect(&mut self) -> Stream {
+        let (client_sender, server_receiver) = mpsc::channel();
+        let (server_sender, client_receiver) = mpsc::channel();
+        let connection = ConnectionId(self.next_id);
+        self.next_id += 1;
+        self.connections
+            .insert(connection, (server_sender, server_receiver));
+        self.events.push_back(ListenerEvent::Connect(connection));
+        Stream {
+            sender: client_sender,
+            receiver: client_receiver,
+        }
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = &ConnectionId> {
+        self.connections.keys()
+    }
+
+    // Closes the connection without reporting it as an event.
+    pub fn disconnect(&mut self, connection: ConnectionId) {
+        self.connections.remove(&connection);
+    }
+
+    pub fn send(&mut self, connection: ConnectionId, data: &[u8]) {
+        if let Some((sender, _)) = self.connections.get(&connection) {
+            let _ = sender.send(data.to_vec());
+        }
+    }
+}
+
+impl Listen for Listener {
+    fn manual_poll(&mut self) {
+        let events = &mut self.events;
+        self.connections.retain(|connection, (_, receiver)| loop {
+            match receiver.try_recv() {
+                Ok(data) => events.push_back(ListenerEvent::Packet(*connection, data)),
+                Err(TryRecvError::Empty) => break true,
+                Err(TryRecvError::Disconnected) => {
+                    events.push_back(ListenerEvent::Disconnect(*connection));
+                    break false;
+                }
+            }
+        });
+    }
+
+    fn recv(&mut self) -> Option<ListenerEvent> {
+        self.events.pop_front()
+    }
+
+    fn disconnect(&mut self, connection: ConnectionId) {
+        Listener::disconnect(self, connection);
+    }
+}
+
+impl SynchronizeOutbound<ConnectionId> for Listener {
+    fn synchronize(&mut self, bound: ConnectionId, data: &[u8]) {
+        self.send(bound, data);
+    }
+}
+
+// The listener is polled once its queue is drained. Only packets are returned, connection
+// events are discarded. Use `Server::poll_connections` to also drop rejected and closed
+// connections.
+impl SynchronizeInbound<ConnectionId> for Listener {
+    fn receive(&mut self) -> Option<(ConnectionId, Vec<u8>)> {
+        let mut polled = false;
+        loop {
+            match self.recv() {
+                Some(ListenerEvent::Packet(connection, data)) => return Some((connection, data)),
+                Some(_) => {}
+                None if polled => return None,
+                None => {
+                    self.manual_poll();
+                    polled = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(listener: &mut Listener) -> Vec<ListenerEvent> {
+        listener.manual_poll();
+        std::iter::from_fn(|| listener.recv()).collect()
+    }
+
+    #[test]
+    fn packets_and_disconnections_are_reported() {
+        let mut listener = Listener::new();
+        let mut stream = listener.connect();
+        let connection = ConnectionId(0);
+        assert_eq!(
+            events(&mut listener),
+            vec![ListenerEvent::Connect(connection)]
+        );
+
+        stream.send(b"ping");
+        let packet = ListenerEvent::Packet(connection, b"ping".to_vec());
+        assert_eq!(events(&mut listener), vec![packet]);
+        listener.send(connection, b"pong");
+        assert_eq!(stream.recv(), Some(b"pong".to_vec()));
+        assert_eq!(stream.recv(), None);
+
+        drop(stream);
+        let disconnect = ListenerEvent::Disconnect(connection);
+        assert_eq!(events(&mut listener), vec![disconnect]);
+        assert_eq!(listener.connections().count(), 0);
+    }
+
+    #[test]
+    fn disconnected_streams_get_what_was_sent_before() {
+        let mut listener = Listener::new();
+        let mut stream = listener.connect();
+        let connection = ConnectionId(0);
+        events(&mut listener);
+
+        listener.send(connection, b"bye");
+        listener.disconnect(connection);
+        listener.send(connection, b"lost");
+        stream.send(b"ignored");
+        assert!(events(&mut listener).is_empty());
+        assert_eq!(stream.recv(), Some(b"bye".to_vec()));
+        assert_eq!(stream.recv(), None);
+    }
+
+    #[test]
+    fn receiving_skips_connection_events() {
+        let mut listener = Listener::new();
+        let mut first = listener.connect();
+        let mut second = listener.connect();
+        first.send(b"1");
+        second.send(b"2");
+        let mut packets = std::iter::from_fn(|| listener.receive()).collect::<Vec<_>>();
+        packets.sort();
+        let expected = vec![
+            (ConnectionId(0), b"1".to_vec()),
+            (ConnectionId(1), b"2".to_vec()),
+        ];
+        assert_eq!(packets, expected);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::codec::Codec;
+use crate::server::{Server, ServerEvent};
+use crate::SynchronizeOutbound;
+use std::fmt;
+use std::hash::Hash;
+
+pub mod memory;
+pub mod multi;
+// Browsers have no raw sockets.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tcp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod udp;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionId(pub u64);
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerEvent<C = ConnectionId> {
+    Connect(C),
+    Packet(C, Vec<u8>),
+    Disconnect(C),
+}
+
+// Transports that report connections as they come and go. Stream oriented ones identify them
+// in the order they were accepted in.
+pub trait Listen<C = ConnectionId> {
+    fn manual_poll(&mut self);
+
+    fn recv(&mut self) -> Option<ListenerEvent<C>>;
+
+    // Closes the connection once the packets sent to it are flushed, without reporting it as
+    // an event.
+    fn disconnect(&mut self, connection: C);
+}
+
+impl<S, C, K> Server<S, C, K>
+where
+    S: SynchronizeOutbound<C> + Listen<C>,
+    C: 'static + Eq + Hash + Clone + Send,
+    K: Codec,
+{
+    // Connections are only registered once their handshake is accepted. Peers whose handshake
+    // is rejected are disconnected after the rejection is sent, closed connections are removed.
+    pub fn poll_connections(&mut self) {
+        self.node.node.manual_poll();
+        while let Some(event) = self.node.node.recv() {
+            match event {
+                ListenerEvent::Connect(_) => {}
+                ListenerEvent::Packet(connection, data) => {
+                    let start = self.events.len();
+                    self.synchronize_inbound(connection.clone(), data);
+                    let rejected = self.events[start..].iter().any(|event| {
+                        matches!(event, ServerEvent::HandshakeRejected(c, _) if *c == connection)
+                    });
+                    if rejected {
+                        self.node.node.disconnect(connection);
+                    }
+                }
+                ListenerEvent::Disconnect(connection) => self.remove_connection(connection),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::ConnectionState;
+    use crate::testing::{self, TestServer};
+
+    fn events(server: &mut TestServer) -> Vec<String> {
+        let events = server.drain_events().map(|event| match event {
+            ServerEvent::Connected(connection) => format!("connected {}", connection),
+            ServerEvent::Disconnected(connection) => format!("disconnected {}", connection),
+            ServerEvent::HandshakeRejected(connection, _) => format!("rejected {}", connection),
+            ServerEvent::Rejected(_) => "rejected resource".into(),
+        });
+        events.collect()
+    }
+
+    #[test]
+    fn connections_are_registered_by_their_handshake() {
+        let mut server = testing::server();
+        let mut client = testing::client(&mut server);
+        server.poll_connections();
+        assert!(events(&mut server).is_empty());
+        assert!(!server.is_connected(ConnectionId(0)));
+
+        testing::connect(&mut server, &mut client);
+        assert_eq!(events(&mut server), vec!["connected #0"]);
+        assert!(server.is_connected(ConnectionId(0)));
+    }
+
+    #[test]
+    fn rejected_peers_are_disconnected() {
+        let mut server = testing::server();
+        let mut client = testing::client(&mut server);
+        client.set_application_id("other".into());
+        client.handshake();
+        testing::pump(&mut server, &mut [&mut client]);
+
+        assert_eq!(events(&mut server), vec!["rejected #0"]);
+        assert!(matches!(client.state(), ConnectionState::Rejected(_)));
+        assert_eq!(server.node.node.connections().count(), 0);
+    }
+
+    #[test]
+    fn closed_connections_are_removed() {
+        let mut server = testing::server();
+        let mut client = testing::client(&mut server);
+        testing::connect(&mut server, &mut client);
+        events(&mut server);
+
+        drop(client);
+        server.poll_connections();
+        assert_eq!(events(&mut server), vec!["disconnected #0"]);
+        assert!(!server.is_connected(ConnectionId(0)));
+    }
+}
@@ -0,0 +1,197 @@
+use super::{Listen, ListenerEvent};
+use crate::{Reliability, SynchronizeInbound, SynchronizeOutbound};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Identifies a connection of either transport. Nesting it combines more than two, for instance
+// `MultiConnection<SocketAddr, MultiConnection<ConnectionId, ConnectionId>>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MultiConnection<A, B> {
+    First(A),
+    Second(B),
+}
+
+impl<A: fmt::Display, B: fmt::Display> fmt::Display for MultiConnection<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiConnection::First(connection) => connection.fmt(f),
+            MultiConnection::Second(connection) => connection.fmt(f),
+        }
+    }
+}
+
+// Serves the connections of two transports, routing outbound packets to the transport the
+// connection belongs to. Receiving alternates between them so that a busy one can't starve the
+// other.
+pub struct MultiTransport<S, T> {
+    pub first: S,
+    pub second: T,
+    second_next: bool,
+}
+
+impl<S, T> MultiTransport<S, T> {
+    pub fn new(first: S, second: T) -> Self {
+        Self {
+            first,
+            second,
+            second_next: false,
+        }
+    }
+
+    // Asks the transport whose turn it is first and hands the turn over.
+    fn alternate<R>(
+        &mut self,
+        first: impl FnOnce(&mut S) -> Option<R>,
+        second: impl FnOnce(&mut T) -> Option<R>,
+    ) -> Option<R> {
+        self.second_next = !self.second_next;
+        if self.second_next {
+            first(&mut self.first).or_else(|| second(&mut self.second))
+        } else {
+            second(&mut self.second).or_else(|| first(&mut self.first))
+        }
+    }
+}
+
+impl<S, T, A, B> SynchronizeOutbound<MultiConnection<A, B>> for MultiTransport<S, T>
+where
+    S: SynchronizeOutbound<A>,
+    T: SynchronizeOutbound<B>,
+{
+    fn synchronize(&mut self, bound: MultiConnection<A, B>, data: &[u8]) {
+        match bound {
+            MultiConnection::First(connection) => self.first.synchronize(connection, data),
+            MultiConnection::Second(connection) => self.second.synchronize(connection, data),
+        }
+    }
+
+    fn synchronize_with(
+        &mut self,
+        bound: MultiConnection<A, B>,
+        data: &[u8],
+        reliability: Reliability,
+    ) {
+        match bound {
+            MultiConnection::First(connection) => {
+                self.first.synchronize_with(connection, data, reliability)
+            }
+            MultiConnection::Second(connection) => {
+                self.second.synchronize_with(connection, data, reliability)
+            }
+        }
+    }
+}
+
+impl<S, T, A, B> SynchronizeInbound<MultiConnection<A, B>> for MultiTransport<S, T>
+where
+    S: SynchronizeInbound<A>,
+    T: SynchronizeInbound<B>,
+{
+    fn receive(&mut self) -> Option<(MultiConnection<A, B>, Vec<u8>)> {
+        self.alternate(
+            |first| {
+                let (connection, data) = first.receive()?;
+                Some((MultiConnection::First(connection), data))
+            },
+            |second| {
+                let (connection, data) = second.receive()?;
+                Some((MultiConnection::Second(connection), data))
+            },
+        )
+    }
+}
+
+impl<S, T, A, B> Listen<MultiConnection<A, B>> for MultiTransport<S, T>
+where
+    S: Listen<A>,
+    T: Listen<B>,
+{
+    fn manual_poll(&mut self) {
+        self.first.manual_poll();
+        self.second.manual_poll();
+    }
+
+    fn recv(&mut self) -> Option<ListenerEvent<MultiConnection<A, B>>> {
+        self.alternate(
+            |first| Some(wrap(first.recv()?, MultiConnection::First)),
+            |second| Some(wrap(second.recv()?, MultiConnection::Second)),
+        )
+    }
+
+    fn disconnect(&mut self, connection: MultiConnection<A, B>) {
+        match connection {
+            MultiConnection::First(connection) => self.first.disconnect(connection),
+            MultiConnection::Second(connection) => self.second.disconnect(connection),
+        }
+    }
+}
+
+fn wrap<C, M>(event: ListenerEvent<C>, connection: impl Fn(C) -> M) -> ListenerEvent<M> {
+    match event {
+        ListenerEvent::Connect(c) => ListenerEvent::Connect(connection(c)),
+        ListenerEvent::Packet(c, data) => ListenerEvent::Packet(connection(c), data),
+        ListenerEvent::Disconnect(c) => ListenerEvent::Disconnect(connection(c)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::diff::FullOnly;
+    use crate::resources::Resources;
+    use crate::server::Server;
+    use crate::transport::{memory, ConnectionId};
+
+    type Multi = MultiTransport<memory::Listener, memory::Listener>;
+
+    type Connection = MultiConnection<ConnectionId, ConnectionId>;
+
+    #[test]
+    fn receiving_alternates_between_transports() {
+        let mut transport = MultiTransport::new(memory::Listener::new(), memory::Listener::new());
+        let mut first = transport.first.connect();
+        let mut second = transport.second.connect();
+        for _ in 0..3 {
+            first.send(b"first");
+            second.send(b"second");
+        }
+        let received = std::iter::from_fn(|| transport.receive());
+        let kinds = received.map(|(connection, _)| match connection {
+            MultiConnection::First(_) => 1,
+            MultiConnection::Second(_) => 2,
+        });
+        assert_eq!(kinds.collect::<Vec<_>>(), vec![1, 2, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn servers_serve_clients_of_both_transports() {
+        let transport = MultiTransport::new(memory::Listener::new(), memory::Listener::new());
+        let mut server = Server::<Multi, Connection>::new(transport);
+        let resources = server.resources_mut();
+        resources.register_outbound_with("count".into(), 0u32, FullOnly);
+        let mut clients: Vec<Client<memory::Stream, ()>> = vec![
+            Client::new(server.node.node.first.connect(), ()),
+            Client::new(server.node.node.second.connect(), ()),
+        ];
+        for client in clients.iter_mut() {
+            let resources = client.resources_mut();
+            resources.register_inbound_with("count".into(), 0u32, FullOnly);
+            client.handshake();
+            client.synchronize_outbound();
+        }
+        server.poll_connections();
+        assert!(server.is_connected(MultiConnection::First(ConnectionId(0))));
+        assert!(server.is_connected(MultiConnection::Second(ConnectionId(0))));
+
+        let resources = server.resources_mut();
+        **resources.outbound_mut::<u32>("count".into()).unwrap() = 5;
+        server.synchronize_outbound();
+        for client in clients.iter_mut() {
+            client.receive_inbound();
+            assert!(client.is_connected());
+            let count = client.resources().inbound::<u32>("count".into()).unwrap();
+            assert_eq!(**count, 5);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use super::{ConnectionId, Listen, ListenerEvent};
+use crate::{SynchronizeInbound, SynchronizeOutbound};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+const LENGTH_SIZE: usize = 4;
+// Peers announcing larger frames are disconnected.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+const READ_SIZE: usize = 4096;
+// Peers that don't read what is sent to them are disconnected once this much is buffered.
+const MAX_OUTGOING_SIZE: usize = 4 * MAX_FRAME_SIZE;
+
+// A non blocking stream of packets, each prefixed by its length as a big endian u32. Writes
+// that would block are buffered until the next flush.
+pub struct Stream {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    // Start of the first frame in `incoming` that wasn't returned yet.
+    read: usize,
+    outgoing: Vec<u8>,
+    closed: bool,
+}
+
+impl Stream {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            incoming: Vec::new(),
+            read: 0,
+            outgoing: Vec::new(),
+            closed: false,
+        })
+    }
+
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        Self::new(TcpStream::connect(address)?)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    // Closes the stream when the buffered writes would exceed the limit.
+    pub fn send(&mut self, data: &[u8]) {
+        if self.closed {
+            return;
+        }
+        if self.outgoing.len() + LENGTH_SIZE + data.len() > MAX_OUTGOING_SIZE {
+            self.shutdown();
+            return;
+        }
+        self.outgoing
+            .extend_from_slice(&(data.len() as u32).to_be_bytes());
+        self.outgoing.extend_from_slice(data);
+        self.flush();
+    }
+
+    pub fn flush(&mut self) {
+        let mut written = 0;
+        while !self.closed && written < self.outgoing.len() {
+            match self.stream.write(&self.outgoing[written..]) {
+                Ok(0) => self.closed = true,
+                Ok(len) => written += len,
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => self.closed = true,
+            }
+        }
+        self.outgoing.drain(..written);
+    }
+
+    // Returns the next complete packet, reading whatever the socket has available first.
+    pub fn recv(&mut self) -> Option<Vec<u8>> {
+        if let Some(packet) = self.next_frame() {
+            return Some(packet);
+        }
+        self.incoming.drain(..self.read);
+        self.read = 0;
+        let mut buffer = [0; READ_SIZE];
+        while !self.closed {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => self.closed = true,
+            }
+        }
+        self.next_frame()
+    }
+
+    pub fn shutdown(&mut self) {
+        self.flush();
+        let _ = self.stream.shutdown(Shutdown::Both);
+        self.closed = true;
+    }
+
+    // Frames are consumed by moving the read offset, the buffer is compacted before reading.
+    fn next_frame(&mut self) -> Option<Vec<u8>> {
+        let incoming = &self.incoming[self.read..];
+        if incoming.len() < LENGTH_SIZE {
+            return None;
+        }
+        let mut length = [0; LENGTH_SIZE];
+        length.copy_from_slice(&incoming[..LENGTH_SIZE]);
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_FRAME_SIZE {
+            self.shutdown();
+            return None;
+        }
+        if incoming.len() < LENGTH_SIZE + length {
+            return None;
+        }
+        let packet = incoming[LENGTH_SIZE..LENGTH_SIZE + length].to_vec();
+        self.read += LENGTH_SIZE + length;
+        Some(packet)
+    }
+}
+
+// Clients only talk to the server, so there is nothing to address.
+impl SynchronizeOutbound<()> for Stream {
+    fn synchronize(&mut self, _bound: (), data: &[u8]) {
+        self.send(data);
+    }
+}
+
+impl SynchronizeInbound<()> for Stream {
+    fn receive(&mut self) -> Option<((), Vec<u8>)> {
+        self.recv().map(|packet| ((), packet))
+    }
+}
+
+// Streams are reliable and ordered, so reliabilities are ignored.
+pub struct Listener {
+    listener: TcpListener,
+    streams: HashMap<ConnectionId, Stream>,
+    next_id: u64,
+    events: VecDeque<ListenerEvent>,
+}
+
+impl Listener {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            streams: HashMap::new(),
+            next_id: 0,
+            events: VecDeque::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn peer_addr(&self, connection: ConnectionId) -> Option<SocketAddr> {
+        self.streams.get(&connection)?.peer_addr().ok()
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = &ConnectionId> {
+        self.streams.keys()
+    }
+
+    // Closes the connection without reporting it as an event.
+    pub fn disconnect(&mut self, connection: ConnectionId) {
+        if let Some(mut stream) = self.streams.remove(&connection) {
+            stream.shutdown();
+        }
+    }
+
+    pub fn send(&mut self, connection: ConnectionId, data: &[u8]) {
+        if let Some(stream) = self.streams.get_mut(&connection) {
+            stream.send(data);
+        }
+    }
+}
+
+impl Listen for Listener {
+    // Accepts pending connections, flushes buffered writes and reads complete packets.
+    fn manual_poll(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Ok(stream) = Stream::new(stream) {
+                        let connection = ConnectionId(self.next_id);
+                        self.next_id += 1;
+                        self.streams.insert(connection, stream);
+                        self.events.push_back(ListenerEvent::Connect(connection));
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => break,
+            }
+        }
+        let events = &mut self.events;
+        self.streams.retain(|connection, stream| {
+            stream.flush();
+            while let Some(packet) = stream.recv() {
+                events.push_back(ListenerEvent::Packet(*connection, packet));
+            }
+            if stream.is_closed() {
+                events.push_back(ListenerEvent::Disconnect(*connection));
+            }
+            !stream.is_closed()
+        });
+    }
+
+    fn recv(&mut self) -> Option<ListenerEvent> {
+        self.events.pop_front()
+    }
+
+    fn disconnect(&mut self, connection: ConnectionId) {
+        Listener::disconnect(self, connection);
+    }
+}
+
+impl SynchronizeOutbound<ConnectionId> for Listener {
+    fn synchronize(&mut self, bound: ConnectionId, data: &[u8]) {
+        self.send(bound, data);
+    }
+}
+
+// The listener is polled once its queue is drained. Only packets are returned, connection
+// events are discarded. Use `Server::poll_connections` to also drop rejected and closed
+// connections.
+impl SynchronizeInbound<ConnectionId> for Listener {
+    fn receive(&mut self) -> Option<(ConnectionId, Vec<u8>)> {
+        let mut polled = false;
+        loop {
+            match self.recv() {
+                Some(ListenerEvent::Packet(connection, data)) => return Some((connection, data)),
+                Some(_) => {}
+                None if polled => return None,
+                None => {
+                    self.manual_poll();
+                    polled = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn listener() -> Listener {
+        Listener::bind("127.0.0.1:0").unwrap()
+    }
+
+    // Waits for the bytes in flight on loopback.
+    fn poll(listener: &mut Listener) -> Vec<ListenerEvent> {
+        thread::sleep(Duration::from_millis(20));
+        listener.manual_poll();
+        std::iter::from_fn(|| listener.recv()).collect()
+    }
+
+    fn wait(stream: &mut Stream) -> Option<Vec<u8>> {
+        thread::sleep(Duration::from_millis(20));
+        stream.recv()
+    }
+
+    #[test]
+    fn frames_are_exchanged_in_order() {
+        let mut listener = listener();
+        let mut stream = Stream::connect(listener.local_addr().unwrap()).unwrap();
+        assert_eq!(
+            poll(&mut listener),
+            vec![ListenerEvent::Connect(ConnectionId(0))]
+        );
+
+        for data in [&b"first"[..], b"", b"third"].iter() {
+            stream.send(data);
+        }
+        let packets = vec![
+            ListenerEvent::Packet(ConnectionId(0), b"first".to_vec()),
+            ListenerEvent::Packet(ConnectionId(0), Vec::new()),
+            ListenerEvent::Packet(ConnectionId(0), b"third".to_vec()),
+        ];
+        assert_eq!(poll(&mut listener), packets);
+
+        listener.send(ConnectionId(0), b"reply");
+        assert_eq!(wait(&mut stream), Some(b"reply".to_vec()));
+        assert_eq!(stream.recv(), None);
+    }
+
+    #[test]
+    fn partial_frames_wait_for_the_rest() {
+        let mut listener = listener();
+        let mut raw = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        poll(&mut listener);
+
+        let mut frames = Vec::new();
+        for data in [&b"one"[..], b"two"].iter() {
+            frames.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            frames.extend_from_slice(data);
+        }
+        let mut received = Vec::new();
+        for byte in frames.iter() {
+            raw.write_all(&[*byte]).unwrap();
+            received.extend(poll(&mut listener));
+        }
+        let packets = vec![
+            ListenerEvent::Packet(ConnectionId(0), b"one".to_vec()),
+            ListenerEvent::Packet(ConnectionId(0), b"two".to_vec()),
+        ];
+        assert_eq!(received, packets);
+    }
+
+    #[test]
+    fn oversized_frames_disconnect_the_peer() {
+        let mut listener = listener();
+        let mut raw = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        poll(&mut listener);
+
+        let length = MAX_FRAME_SIZE as u32 + 1;
+        raw.write_all(&length.to_be_bytes()).unwrap();
+        let events = poll(&mut listener);
+        assert_eq!(events, vec![ListenerEvent::Disconnect(ConnectionId(0))]);
+        assert_eq!(listener.connections().count(), 0);
+    }
+
+    #[test]
+    fn peers_that_stop_reading_are_disconnected() {
+        let mut listener = listener();
+        let _stream = Stream::connect(listener.local_addr().unwrap()).unwrap();
+        poll(&mut listener);
+
+        let frame = vec![0; MAX_FRAME_SIZE];
+        for _ in 0..MAX_OUTGOING_SIZE / MAX_FRAME_SIZE + 1 {
+            listener.send(ConnectionId(0), &frame);
+        }
+        let events = poll(&mut listener);
+        assert_eq!(events, vec![ListenerEvent::Disconnect(ConnectionId(0))]);
+    }
+}
@@ -0,0 +1,578 @@
+use super::{Listen, ListenerEvent};
+use crate::{Reliability, SynchronizeInbound, SynchronizeOutbound};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+const PROTOCOL_ID: u16 = 0x525a;
+const HEARTBEAT: u8 = 0;
+const DATA: u8 = 1;
+// Set on the kind byte once the sender received something to acknowledge.
+const HAS_ACK: u8 = 0x80;
+const HEADER_SIZE: usize = 11;
+const DATA_HEADER_SIZE: usize = 3;
+const MAX_DATAGRAM_SIZE: usize = 65536;
+// The largest payload an IPv4 datagram can carry once the headers are added.
+pub const MAX_PAYLOAD_SIZE: usize = 65507 - HEADER_SIZE - DATA_HEADER_SIZE;
+// Bounds the memory used by duplicate detection and by ordered packets waiting for a gap.
+const WINDOW: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub heartbeat_interval: Duration,
+    pub idle_timeout: Duration,
+    pub resend_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_millis(200),
+            idle_timeout: Duration::from_secs(5),
+            resend_timeout: Duration::from_millis(300),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketEvent {
+    Connect(SocketAddr),
+    Packet(SocketAddr, Vec<u8>),
+    Timeout(SocketAddr),
+}
+
+struct Pending {
+    sequence: u16,
+    sent_at: Instant,
+    reliability: Reliability,
+    id: u16,
+    data: Vec<u8>,
+}
+
+struct Connection {
+    established: bool,
+    last_received: Instant,
+    last_sent: Instant,
+    sequence: u16,
+    remote_sequence: Option<u16>,
+    received: u32,
+    unacked: u32,
+    next_ids: [u16; 5],
+    pending: Vec<Pending>,
+    latest: [Option<u16>; 5],
+    recent: VecDeque<u16>,
+    expected: u16,
+    buffered: HashMap<u16, Vec<u8>>,
+}
+
+impl Connection {
+    fn new(now: Instant) -> Self {
+        Self {
+            established: false,
+            last_received: now,
+            last_sent: now,
+            sequence: 0,
+            remote_sequence: None,
+            received: 0,
+            unacked: 0,
+            next_ids: [0; 5],
+            pending: Vec::new(),
+            latest: [None; 5],
+            recent: VecDeque::new(),
+            expected: 0,
+            buffered: HashMap::new(),
+        }
+    }
+
+    // Records a received sequence number, returning false for duplicates.
+    fn receive_sequence(&mut self, sequence: u16) -> bool {
+        let remote = match self.remote_sequence {
+            Some(remote) => remote,
+            None => {
+                self.remote_sequence = Some(sequence);
+                return true;
+            }
+        };
+        if is_newer(sequence, remote) {
+            let shift = u32::from(sequence.wrapping_sub(remote));
+            self.received = match shift {
+                1..=31 => self.received << shift | 1 << (shift - 1),
+                32 => 1 << 31,
+                _ => 0,
+            };
+            self.remote_sequence = Some(sequence);
+            return true;
+        }
+        let distance = remote.wrapping_sub(sequence);
+        if distance == 0 || distance > 32 {
+            return distance > 32;
+        }
+        let bit = 1 << (distance - 1);
+        let duplicate = self.received & bit != 0;
+        self.received |= bit;
+        !duplicate
+    }
+
+    fn acknowledge(&mut self, ack: u16, bits: u32) {
+        self.pending.retain(|pending| {
+            let distance = ack.wrapping_sub(pending.sequence);
+            let acked = distance == 0 || (distance <= 32 && bits & 1 << (distance - 1) != 0);
+            !acked
+        });
+    }
+
+    // Ordered packets too far ahead to be buffered are neither acknowledged nor delivered, so
+    // the sender keeps resending them until the gap is filled.
+    fn accepts(&self, reliability: Reliability, id: u16) -> bool {
+        reliability != Reliability::ReliableOrdered
+            || !is_newer(id, self.expected)
+            || usize::from(id.wrapping_sub(self.expected)) < WINDOW
+    }
+
+    // Returns the packets that can be handed to the application, in order.
+    fn deliver(&mut self, reliability: Reliability, id: u16, data: &[u8]) -> Vec<Vec<u8>> {
+        let stream = stream(reliability);
+        match reliability {
+            Reliability::Unreliable => vec![data.to_vec()],
+            Reliability::UnreliableSequenced | Reliability::ReliableSequenced => {
+                match self.latest[stream] {
+                    Some(latest) if !is_newer(id, latest) => Vec::new(),
+                    _ => {
+                        self.latest[stream] = Some(id);
+                        vec![data.to_vec()]
+                    }
+                }
+            }
+            Reliability::ReliableUnordered => {
+                if self.recent.contains(&id) {
+                    return Vec::new();
+                }
+                if self.recent.len() == WINDOW {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(id);
+                vec![data.to_vec()]
+            }
+            Reliability::ReliableOrdered => {
+                // Packets are acknowledged on arrival, so every newer one has to be kept.
+                if id != self.expected {
+                    if is_newer(id, self.expected) {
+                        self.buffered.insert(id, data.to_vec());
+                    }
+                    return Vec::new();
+                }
+                let mut packets = vec![data.to_vec()];
+                self.expected = self.expected.wrapping_add(1);
+                while let Some(data) = self.buffered.remove(&self.expected) {
+                    packets.push(data);
+                    self.expected = self.expected.wrapping_add(1);
+                }
+                packets
+            }
+        }
+    }
+}
+
+// A minimal connection oriented protocol over UDP. Every datagram carries a sequence number
+// and acknowledges the last 33 received ones, reliable packets are resent until acknowledged.
+pub struct Socket {
+    socket: UdpSocket,
+    config: Config,
+    connections: HashMap<SocketAddr, Connection>,
+    events: VecDeque<SocketEvent>,
+    buffer: Vec<u8>,
+}
+
+impl Socket {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        Self::bind_with_config(address, Config::default())
+    }
+
+    pub fn bind_with_config<A: ToSocketAddrs>(address: A, config: Config) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            config,
+            connections: HashMap::new(),
+            events: VecDeque::new(),
+            buffer: vec![0; MAX_DATAGRAM_SIZE],
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.connections.keys()
+    }
+
+    pub fn is_connected(&self, address: SocketAddr) -> bool {
+        self.connections.contains_key(&address)
+    }
+
+    // Forgets the connection without notifying the peer.
+    pub fn disconnect(&mut self, address: SocketAddr) {
+        self.connections.remove(&address);
+    }
+
+    pub fn recv(&mut self) -> Option<SocketEvent> {
+        self.events.pop_front()
+    }
+
+    // Payloads larger than `MAX_PAYLOAD_SIZE` are rejected, as they could never be delivered.
+    pub fn send(
+        &mut self,
+        address: SocketAddr,
+        data: &[u8],
+        reliability: Reliability,
+    ) -> io::Result<()> {
+        if data.len() > MAX_PAYLOAD_SIZE {
+            let error = format!("payload of {} bytes doesn't fit a datagram", data.len());
+            return Err(io::Error::new(ErrorKind::InvalidInput, error));
+        }
+        let now = Instant::now();
+        let connection = self
+            .connections
+            .entry(address)
+            .or_insert_with(|| Connection::new(now));
+        let stream = stream(reliability);
+        let id = connection.next_ids[stream];
+        connection.next_ids[stream] = id.wrapping_add(1);
+        let body = Some((reliability, id, data));
+        let sequence = send(&self.socket, address, connection, body, now);
+        if is_reliable(reliability) {
+            connection.pending.push(Pending {
+                sequence,
+                sent_at: now,
+                reliability,
+                id,
+                data: data.to_vec(),
+            });
+        }
+        Ok(())
+    }
+
+    // Receives pending datagrams, then resends unacknowledged packets, sends heartbeats and
+    // times out idle connections.
+    pub fn manual_poll(&mut self, now: Instant) {
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((len, address)) => self.handle(address, len, now),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                // Reported on some platforms when a previous datagram was refused.
+                Err(error) if error.kind() == ErrorKind::ConnectionReset => continue,
+                Err(_) => break,
+            }
+        }
+        let config = &self.config;
+        let events = &mut self.events;
+        let socket = &self.socket;
+        self.connections.retain(|address, connection| {
+            if now.duration_since(connection.last_received) >= config.idle_timeout {
+                events.push_back(SocketEvent::Timeout(*address));
+                return false;
+            }
+            let mut pending = std::mem::take(&mut connection.pending);
+            for packet in pending.iter_mut() {
+                if now.duration_since(packet.sent_at) >= config.resend_timeout {
+                    let body = Some((packet.reliability, packet.id, &packet.data[..]));
+                    packet.sequence = send(socket, *address, connection, body, now);
+                    packet.sent_at = now;
+                }
+            }
+            connection.pending = pending;
+            let heartbeat = now.duration_since(connection.last_sent) >= config.heartbeat_interval;
+            if heartbeat || connection.unacked > 0 {
+                send(socket, *address, connection, None, now);
+            }
+            true
+        });
+    }
+
+    fn handle(&mut self, address: SocketAddr, len: usize, now: Instant) {
+        let data = &self.buffer[..len];
+        if len < HEADER_SIZE || u16::from_be_bytes([data[0], data[1]]) != PROTOCOL_ID {
+            return;
+        }
+        let kind = data[2] & !HAS_ACK;
+        let has_ack = data[2] & HAS_ACK != 0;
+        let sequence = u16::from_be_bytes([data[3], data[4]]);
+        let ack = u16::from_be_bytes([data[5], data[6]]);
+        let bits = u32::from_be_bytes([data[7], data[8], data[9], data[10]]);
+        let connection = self
+            .connections
+            .entry(address)
+            .or_insert_with(|| Connection::new(now));
+        connection.last_received = now;
+        if !connection.established {
+            connection.established = true;
+            self.events.push_back(SocketEvent::Connect(address));
+        }
+        if has_ack {
+            connection.acknowledge(ack, bits);
+        }
+        let body = match kind {
+            DATA if len >= HEADER_SIZE + DATA_HEADER_SIZE => {
+                let body = &data[HEADER_SIZE..];
+                let id = u16::from_be_bytes([body[1], body[2]]);
+                match read_reliability(body[0]) {
+                    Some(reliability) => Some((reliability, id)),
+                    None => return,
+                }
+            }
+            DATA => return,
+            _ => None,
+        };
+        if let Some((reliability, id)) = body {
+            if !connection.accepts(reliability, id) {
+                return;
+            }
+        }
+        if !connection.receive_sequence(sequence) {
+            return;
+        }
+        // The ack bitfield only covers 33 packets, so bursts are acknowledged as they arrive.
+        connection.unacked += 1;
+        if connection.unacked > 32 {
+            send(&self.socket, address, connection, None, now);
+        }
+        let (reliability, id) = match body {
+            Some(body) => body,
+            None => return,
+        };
+        let data = &self.buffer[HEADER_SIZE + DATA_HEADER_SIZE..len];
+        for packet in connection.deliver(reliability, id, data) {
+            self.events.push_back(SocketEvent::Packet(address, packet));
+        }
+    }
+}
+
+impl Listen<SocketAddr> for Socket {
+    fn manual_poll(&mut self) {
+        Socket::manual_poll(self, Instant::now());
+    }
+
+    fn recv(&mut self) -> Option<ListenerEvent<SocketAddr>> {
+        Some(match Socket::recv(self)? {
+            SocketEvent::Connect(address) => ListenerEvent::Connect(address),
+            SocketEvent::Packet(address, data) => ListenerEvent::Packet(address, data),
+            SocketEvent::Timeout(address) => ListenerEvent::Disconnect(address),
+        })
+    }
+
+    fn disconnect(&mut self, address: SocketAddr) {
+        Socket::disconnect(self, address);
+    }
+}
+
+// Packets are sent right away, received ones are only read when polling. Oversized packets
+// are dropped, use `send` to handle them.
+impl SynchronizeOutbound<SocketAddr> for Socket {
+    fn synchronize(&mut self, bound: SocketAddr, data: &[u8]) {
+        self.synchronize_with(bound, data, Reliability::ReliableOrdered);
+    }
+
+    fn synchronize_with(&mut self, bound: SocketAddr, data: &[u8], reliability: Reliability) {
+        let _ = self.send(bound, data, reliability);
+    }
+}
+
+// Polls the socket once the queued events are drained. Only packets are returned, connection
+// events are discarded.
+impl SynchronizeInbound<SocketAddr> for Socket {
+    fn receive(&mut self) -> Option<(SocketAddr, Vec<u8>)> {
+        if self.events.is_empty() {
+            self.manual_poll(Instant::now());
+        }
+        loop {
+            if let SocketEvent::Packet(address, data) = self.recv()? {
+                return Some((address, data));
+            }
+        }
+    }
+}
+
+fn send(
+    socket: &UdpSocket,
+    address: SocketAddr,
+    connection: &mut Connection,
+    body: Option<(Reliability, u16, &[u8])>,
+    now: Instant,
+) -> u16 {
+    let sequence = connection.sequence;
+    connection.sequence = sequence.wrapping_add(1);
+    connection.last_sent = now;
+    connection.unacked = 0;
+    let mut datagram = Vec::with_capacity(HEADER_SIZE + DATA_HEADER_SIZE);
+    datagram.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+    let kind = if body.is_some() { DATA } else { HEARTBEAT };
+    match connection.remote_sequence {
+        Some(_) => datagram.push(kind | HAS_ACK),
+        None => datagram.push(kind),
+    }
+    datagram.extend_from_slice(&sequence.to_be_bytes());
+    let ack = connection.remote_sequence.unwrap_or(0);
+    datagram.extend_from_slice(&ack.to_be_bytes());
+    datagram.extend_from_slice(&connection.received.to_be_bytes());
+    if let Some((reliability, id, data)) = body {
+        datagram.push(stream(reliability) as u8);
+        datagram.extend_from_slice(&id.to_be_bytes());
+        datagram.extend_from_slice(data);
+    }
+    // Lost like any other datagram if the socket is busy, reliable packets get resent.
+    let _ = socket.send_to(&datagram, address);
+    sequence
+}
+
+fn is_newer(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+fn is_reliable(reliability: Reliability) -> bool {
+    !matches!(
+        reliability,
+        Reliability::Unreliable | Reliability::UnreliableSequenced
+    )
+}
+
+fn stream(reliability: Reliability) -> usize {
+    match reliability {
+        Reliability::Unreliable => 0,
+        Reliability::UnreliableSequenced => 1,
+        Reliability::ReliableUnordered => 2,
+        Reliability::ReliableOrdered => 3,
+        Reliability::ReliableSequenced => 4,
+    }
+}
+
+fn read_reliability(stream: u8) -> Option<Reliability> {
+    match stream {
+        0 => Some(Reliability::Unreliable),
+        1 => Some(Reliability::UnreliableSequenced),
+        2 => Some(Reliability::ReliableUnordered),
+        3 => Some(Reliability::ReliableOrdered),
+        4 => Some(Reliability::ReliableSequenced),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn socket() -> Socket {
+        Socket::bind("127.0.0.1:0").unwrap()
+    }
+
+    // Waits for the datagrams in flight on localhost.
+    fn poll(socket: &mut Socket, now: Instant) -> Vec<SocketEvent> {
+        thread::sleep(Duration::from_millis(20));
+        socket.manual_poll(now);
+        std::iter::from_fn(|| socket.recv()).collect()
+    }
+
+    // Drops the next datagram, as if it was lost.
+    fn lose(socket: &mut Socket) {
+        thread::sleep(Duration::from_millis(20));
+        socket.socket.recv_from(&mut socket.buffer).unwrap();
+    }
+
+    fn packets(events: Vec<SocketEvent>) -> Vec<Vec<u8>> {
+        let packets = events.into_iter().filter_map(|event| match event {
+            SocketEvent::Packet(_, data) => Some(data),
+            _ => None,
+        });
+        packets.collect()
+    }
+
+    #[test]
+    fn reliable_packets_are_acknowledged() {
+        let (mut a, mut b) = (socket(), socket());
+        let (a_address, b_address) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        let now = Instant::now();
+        a.send(b_address, b"ping", Reliability::ReliableOrdered)
+            .unwrap();
+        let events = poll(&mut b, now);
+        assert_eq!(events[0], SocketEvent::Connect(a_address));
+        assert_eq!(events[1], SocketEvent::Packet(a_address, b"ping".to_vec()));
+        assert_eq!(a.connections[&b_address].pending.len(), 1);
+        poll(&mut a, now);
+        assert!(a.connections[&b_address].pending.is_empty());
+    }
+
+    #[test]
+    fn lost_packets_are_resent() {
+        let (mut a, mut b) = (socket(), socket());
+        let b_address = b.local_addr().unwrap();
+        let now = Instant::now();
+        a.send(b_address, b"ping", Reliability::ReliableUnordered)
+            .unwrap();
+        lose(&mut b);
+        poll(&mut a, now);
+        assert!(packets(poll(&mut b, now)).is_empty());
+        poll(&mut a, now + Config::default().resend_timeout * 2);
+        assert_eq!(packets(poll(&mut b, now)), vec![b"ping".to_vec()]);
+    }
+
+    #[test]
+    fn ordered_packets_wait_for_missing_ones() {
+        let (mut a, mut b) = (socket(), socket());
+        let b_address = b.local_addr().unwrap();
+        let now = Instant::now();
+        for data in [b"0", b"1", b"2"].iter() {
+            a.send(b_address, *data, Reliability::ReliableOrdered)
+                .unwrap();
+        }
+        lose(&mut b);
+        assert!(packets(poll(&mut b, now)).is_empty());
+        poll(&mut a, now + Config::default().resend_timeout * 2);
+        let expected = vec![b"0".to_vec(), b"1".to_vec(), b"2".to_vec()];
+        assert_eq!(packets(poll(&mut b, now)), expected);
+    }
+
+    #[test]
+    fn ordered_packets_outside_the_window_are_refused() {
+        let mut connection = Connection::new(Instant::now());
+        let ordered = Reliability::ReliableOrdered;
+        let ahead = WINDOW as u16;
+        assert!(connection.accepts(ordered, ahead - 1));
+        assert!(!connection.accepts(ordered, ahead));
+        assert!(connection.accepts(Reliability::ReliableUnordered, ahead));
+        connection.deliver(ordered, 0, b"0");
+        assert!(connection.accepts(ordered, ahead));
+        assert!(connection.accepts(ordered, 0));
+    }
+
+    #[test]
+    fn idle_connections_time_out() {
+        let (mut a, mut b) = (socket(), socket());
+        let a_address = a.local_addr().unwrap();
+        let b_address = b.local_addr().unwrap();
+        let now = Instant::now();
+        a.send(b_address, b"ping", Reliability::Unreliable).unwrap();
+        poll(&mut b, now);
+        assert!(b.is_connected(a_address));
+        let events = poll(&mut b, now + Config::default().idle_timeout);
+        assert_eq!(events, vec![SocketEvent::Timeout(a_address)]);
+        assert!(!b.is_connected(a_address));
+    }
+
+    #[test]
+    fn oversized_payloads_are_rejected() {
+        let mut a = socket();
+        let address = socket().local_addr().unwrap();
+        let data = vec![0; MAX_PAYLOAD_SIZE + 1];
+        let error = a
+            .send(address, &data, Reliability::ReliableOrdered)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(a.connections.is_empty());
+        let data = vec![0; MAX_PAYLOAD_SIZE];
+        a.send(address, &data, Reliability::ReliableOrdered)
+            .unwrap();
+    }
+}
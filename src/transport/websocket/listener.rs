@@ -0,0 +1,265 @@
+use crate::transport::{ConnectionId, Listen, ListenerEvent};
+use crate::{SynchronizeInbound, SynchronizeOutbound};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use tungstenite::handshake::server::{NoCallback, ServerHandshake};
+use tungstenite::handshake::{HandshakeError, MidHandshake};
+use tungstenite::{Error, Message, WebSocket};
+
+type Role = ServerHandshake<TcpStream, NoCallback>;
+
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Accepts WebSocket connections, each packet being sent as one binary message. Connections
+// are only reported once their handshake completed, peers that take longer than the handshake
+// timeout are dropped.
+pub struct Listener {
+    listener: TcpListener,
+    handshakes: Vec<(Instant, MidHandshake<Role>)>,
+    handshake_timeout: Duration,
+    sockets: HashMap<ConnectionId, WebSocket<TcpStream>>,
+    next_id: u64,
+    events: VecDeque<ListenerEvent>,
+}
+
+impl Listener {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            handshakes: Vec::new(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            sockets: HashMap::new(),
+            next_id: 0,
+            events: VecDeque::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn set_handshake_timeout(&mut self, timeout: Duration) {
+        self.handshake_timeout = timeout;
+    }
+
+    pub fn peer_addr(&self, connection: ConnectionId) -> Option<SocketAddr> {
+        self.sockets.get(&connection)?.get_ref().peer_addr().ok()
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = &ConnectionId> {
+        self.sockets.keys()
+    }
+
+    // Closes the connection without reporting it as an event.
+    pub fn disconnect(&mut self, connection: ConnectionId) {
+        if let Some(mut socket) = self.sockets.remove(&connection) {
+            let _ = socket.close(None);
+            let _ = socket.flush();
+        }
+    }
+
+    // Messages that can't be written right away stay buffered until the next poll. Connections
+    // whose buffer is full or that fail otherwise are closed and reported as disconnected.
+    pub fn send(&mut self, connection: ConnectionId, data: &[u8]) {
+        let socket = match self.sockets.get_mut(&connection) {
+            Some(socket) => socket,
+            None => return,
+        };
+        match socket.send(Message::binary(data.to_vec())) {
+            Ok(()) => {}
+            Err(Error::Io(error)) if error.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => {
+                self.disconnect(connection);
+                self.events.push_back(ListenerEvent::Disconnect(connection));
+            }
+        }
+    }
+
+    fn accept(
+        &mut self,
+        started: Instant,
+        handshake: Result<WebSocket<TcpStream>, HandshakeError<Role>>,
+    ) {
+        match handshake {
+            Ok(socket) => {
+                let connection = ConnectionId(self.next_id);
+                self.next_id += 1;
+                self.sockets.insert(connection, socket);
+                self.events.push_back(ListenerEvent::Connect(connection));
+            }
+            Err(HandshakeError::Interrupted(handshake)) => {
+                if started.elapsed() < self.handshake_timeout {
+                    self.handshakes.push((started, handshake));
+                }
+            }
+            Err(HandshakeError::Failure(_)) => {}
+        }
+    }
+}
+
+impl Listen for Listener {
+    // Accepts pending connections, advances handshakes and reads received messages.
+    fn manual_poll(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let configured = stream
+                        .set_nonblocking(true)
+                        .and_then(|()| stream.set_nodelay(true));
+                    if configured.is_ok() {
+                        let handshake = tungstenite::accept(stream);
+                        self.accept(Instant::now(), handshake);
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => break,
+            }
+        }
+        for (started, handshake) in std::mem::take(&mut self.handshakes) {
+            let handshake = handshake.handshake();
+            self.accept(started, handshake);
+        }
+        let events = &mut self.events;
+        self.sockets.retain(|connection, socket| {
+            let open = loop {
+                match socket.read() {
+                    Ok(Message::Binary(data)) => {
+                        events.push_back(ListenerEvent::Packet(*connection, data.to_vec()));
+                    }
+                    Ok(_) => {}
+                    Err(Error::Io(error)) if error.kind() == ErrorKind::WouldBlock => {
+                        break true;
+                    }
+                    Err(_) => break false,
+                }
+            };
+            let flushed = match socket.flush() {
+                Err(Error::Io(error)) => error.kind() == ErrorKind::WouldBlock,
+                Err(_) => false,
+                Ok(()) => true,
+            };
+            if !open || !flushed {
+                events.push_back(ListenerEvent::Disconnect(*connection));
+            }
+            open && flushed
+        });
+    }
+
+    fn recv(&mut self) -> Option<ListenerEvent> {
+        self.events.pop_front()
+    }
+
+    fn disconnect(&mut self, connection: ConnectionId) {
+        Listener::disconnect(self, connection);
+    }
+}
+
+impl SynchronizeOutbound<ConnectionId> for Listener {
+    fn synchronize(&mut self, bound: ConnectionId, data: &[u8]) {
+        self.send(bound, data);
+    }
+}
+
+// The listener is polled once its queue is drained. Only packets are returned, connection
+// events are discarded. Use `Server::poll_connections` to also drop rejected and closed
+// connections.
+impl SynchronizeInbound<ConnectionId> for Listener {
+    fn receive(&mut self) -> Option<(ConnectionId, Vec<u8>)> {
+        let mut polled = false;
+        loop {
+            match self.recv() {
+                Some(ListenerEvent::Packet(connection, data)) => return Some((connection, data)),
+                Some(_) => {}
+                None if polled => return None,
+                None => {
+                    self.manual_poll();
+                    polled = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::thread;
+
+    fn listener() -> Listener {
+        Listener::bind("127.0.0.1:0").unwrap()
+    }
+
+    // Polls until an event arrives, the client runs in its own thread.
+    fn next(listener: &mut Listener) -> ListenerEvent {
+        for _ in 0..500 {
+            listener.manual_poll();
+            if let Some(event) = listener.recv() {
+                return event;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        panic!("no event received");
+    }
+
+    #[test]
+    fn binary_messages_round_trip() {
+        let mut listener = listener();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let client = thread::spawn(move || {
+            let (mut socket, _) = tungstenite::connect(url).unwrap();
+            socket.send(Message::text("ignored")).unwrap();
+            socket.send(Message::binary(b"ping".to_vec())).unwrap();
+            let reply = socket.read().unwrap();
+            socket.close(None).unwrap();
+            while socket.read().is_ok() {}
+            reply
+        });
+
+        let connection = ConnectionId(0);
+        assert_eq!(next(&mut listener), ListenerEvent::Connect(connection));
+        let packet = ListenerEvent::Packet(connection, b"ping".to_vec());
+        assert_eq!(next(&mut listener), packet);
+        listener.send(connection, b"pong");
+        assert_eq!(next(&mut listener), ListenerEvent::Disconnect(connection));
+        assert_eq!(client.join().unwrap(), Message::binary(b"pong".to_vec()));
+    }
+
+    #[test]
+    fn stalled_handshakes_are_dropped() {
+        let mut listener = listener();
+        listener.set_handshake_timeout(Duration::from_millis(20));
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        listener.manual_poll();
+        assert_eq!(listener.handshakes.len(), 1);
+
+        thread::sleep(Duration::from_millis(20));
+        listener.manual_poll();
+        assert!(listener.handshakes.is_empty());
+        let timeout = Some(Duration::from_secs(1));
+        stream.set_read_timeout(timeout).unwrap();
+        assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+        assert_eq!(listener.recv(), None);
+    }
+
+    #[test]
+    fn failed_sends_disconnect() {
+        let mut listener = listener();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let client = thread::spawn(move || tungstenite::connect(url).unwrap());
+        let connection = ConnectionId(0);
+        assert_eq!(next(&mut listener), ListenerEvent::Connect(connection));
+        let _client = client.join().unwrap();
+
+        let socket = listener.sockets.get_mut(&connection).unwrap();
+        let _ = socket.close(None);
+        listener.send(connection, b"late");
+        assert_eq!(listener.recv(), Some(ListenerEvent::Disconnect(connection)));
+        assert_eq!(listener.connections().count(), 0);
+    }
+}
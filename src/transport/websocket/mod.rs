@@ -0,0 +1,11 @@
+// The server side runs natively and the client side in browsers, both exchanging packets as
+// binary messages.
+#[cfg(not(target_arch = "wasm32"))]
+mod listener;
+#[cfg(target_arch = "wasm32")]
+mod stream;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use listener::Listener;
+#[cfg(target_arch = "wasm32")]
+pub use stream::Stream;
@@ -0,0 +1,102 @@
+use crate::{SynchronizeInbound, SynchronizeOutbound};
+use js_sys::Uint8Array;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{BinaryType, MessageEvent, WebSocket};
+
+// A browser WebSocket. Packets sent while connecting are queued until the socket opens and
+// received ones are queued until read.
+pub struct Stream {
+    socket: WebSocket,
+    incoming: Rc<RefCell<VecDeque<Vec<u8>>>>,
+    pending: Rc<RefCell<Vec<Vec<u8>>>>,
+    _on_open: Closure<dyn FnMut()>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl Stream {
+    pub fn connect(url: &str) -> Result<Self, JsValue> {
+        let socket = WebSocket::new(url)?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+        let incoming = Rc::new(RefCell::new(VecDeque::new()));
+        let pending = Rc::new(RefCell::new(Vec::<Vec<u8>>::new()));
+
+        let on_open = {
+            let socket = socket.clone();
+            let pending = Rc::clone(&pending);
+            Closure::<dyn FnMut()>::new(move || {
+                for data in pending.borrow_mut().drain(..) {
+                    let _ = socket.send_with_u8_array(&data);
+                }
+            })
+        };
+        socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+
+        // Text messages aren't part of the protocol and are ignored.
+        let on_message = {
+            let incoming = Rc::clone(&incoming);
+            Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                if let Ok(buffer) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                    let data = Uint8Array::new(&buffer).to_vec();
+                    incoming.borrow_mut().push_back(data);
+                }
+            })
+        };
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        Ok(Self {
+            socket,
+            incoming,
+            pending,
+            _on_open: on_open,
+            _on_message: on_message,
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.socket.ready_state() == WebSocket::OPEN
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let state = self.socket.ready_state();
+        state == WebSocket::CLOSING || state == WebSocket::CLOSED
+    }
+
+    pub fn send(&mut self, data: &[u8]) {
+        match self.socket.ready_state() {
+            WebSocket::CONNECTING => self.pending.borrow_mut().push(data.to_vec()),
+            WebSocket::OPEN => {
+                let _ = self.socket.send_with_u8_array(data);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn recv(&mut self) -> Option<Vec<u8>> {
+        self.incoming.borrow_mut().pop_front()
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.socket.set_onopen(None);
+        self.socket.set_onmessage(None);
+        let _ = self.socket.close();
+    }
+}
+
+// Clients only talk to the server, so there is nothing to address.
+impl SynchronizeOutbound<()> for Stream {
+    fn synchronize(&mut self, _bound: (), data: &[u8]) {
+        self.send(data);
+    }
+}
+
+impl SynchronizeInbound<()> for Stream {
+    fn receive(&mut self) -> Option<((), Vec<u8>)> {
+        self.recv().map(|packet| ((), packet))
+    }
+}
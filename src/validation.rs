@@ -0,0 +1,249 @@
+use crate::codec::Codec;
+use crate::resources::{Discarded, InboundResource, InternalInboundResource};
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict<T> {
+    Accept,
+    Clamp(T),
+    Reject,
+}
+
+pub(crate) trait InternalValidator<C, K: Codec>: Send {
+    // Returns whether the value was clamped, in which case the client's snapshot no longer
+    // matches the server's and its next update has to be sent in full.
+    fn validate(
+        &self,
+        connection: &C,
+        resource: &mut dyn InternalInboundResource<K>,
+        data: &[u8],
+    ) -> Result<bool, Discarded>;
+}
+
+pub(crate) struct Validator<T, F> {
+    validator: F,
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T, F> Validator<T, F> {
+    pub(crate) fn new(validator: F) -> Self {
+        Self {
+            validator,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<C, K, T: 'static, F> InternalValidator<C, K> for Validator<T, F>
+where
+    K: Codec,
+    T: Debug + Clone + DeserializeOwned + Send,
+    F: Fn(&C, &T, &T) -> Verdict<T> + Send,
+{
+    fn validate(
+        &self,
+        connection: &C,
+        resource: &mut dyn InternalInboundResource<K>,
+        data: &[u8],
+    ) -> Result<bool, Discarded> {
+        // The resource may have been registered with another type than the validator.
+        let resource = resource
+            .downcast_mut::<InboundResource<T>>()
+            .ok_or(Discarded::Invalid)?;
+        let (generation, data) = resource.decode::<K>(data)?;
+        match (self.validator)(connection, resource, &data) {
+            Verdict::Accept => {
+                resource.set(generation, data);
+                Ok(false)
+            }
+            Verdict::Clamp(data) => {
+                resource.set(generation, data);
+                Ok(true)
+            }
+            Verdict::Reject => Err(Discarded::Rejected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::XorDelta;
+    use crate::resources::Resources;
+    use crate::server::{InboundIdentifier, ServerEvent};
+    use crate::testing::{self, TestClient, TestServer};
+    use crate::transport::ConnectionId;
+    use serde::{Deserialize, Serialize};
+
+    // Large enough for diffs to be smaller than the full state.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        x: i32,
+        padding: Vec<u8>,
+    }
+
+    impl Position {
+        fn new(x: i32) -> Self {
+            Self {
+                x,
+                padding: vec![1; 64],
+            }
+        }
+    }
+
+    fn identifier() -> InboundIdentifier<ConnectionId> {
+        InboundIdentifier("position".into(), ConnectionId(0))
+    }
+
+    fn received(server: &TestServer) -> i32 {
+        server
+            .resources()
+            .inbound::<Position>(identifier())
+            .unwrap()
+            .x
+    }
+
+    #[test]
+    fn rejected_update_is_resent_in_full() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        resources.register_inbound_with(identifier(), Position::new(0), XorDelta::new());
+        server.register_validator("position".into(), |_, _, new: &Position| {
+            if new.x > 10 {
+                Verdict::Reject
+            } else {
+                Verdict::Accept
+            }
+        });
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_outbound_with("position".into(), Position::new(0), XorDelta::new());
+        testing::connect(&mut server, &mut client);
+
+        let position = || "position".to_string();
+        client
+            .resources_mut()
+            .outbound_mut::<Position>(position())
+            .unwrap()
+            .x = 5;
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(received(&server), 5);
+
+        client
+            .resources_mut()
+            .outbound_mut::<Position>(position())
+            .unwrap()
+            .x = 50;
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(received(&server), 5);
+        let rejected = server
+            .drain_events()
+            .filter(|event| matches!(event, ServerEvent::Rejected(_)))
+            .count();
+        assert_eq!(rejected, 1);
+
+        // Diffed against the rejected state, so it only gets through once resent in full.
+        client
+            .resources_mut()
+            .outbound_mut::<Position>(position())
+            .unwrap()
+            .x = 7;
+        testing::pump(&mut server, &mut [&mut client]);
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(received(&server), 7);
+
+        client
+            .resources_mut()
+            .outbound_mut::<Position>(position())
+            .unwrap()
+            .x = 8;
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(received(&server), 8);
+    }
+
+    #[test]
+    fn clamped_update_is_stored() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        resources.register_inbound_with(identifier(), Position::new(0), XorDelta::new());
+        server.register_validator("position".into(), |_, _, new: &Position| {
+            Verdict::Clamp(Position::new(new.x.min(10)))
+        });
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_outbound_with("position".into(), Position::new(0), XorDelta::new());
+        testing::connect(&mut server, &mut client);
+
+        client
+            .resources_mut()
+            .outbound_mut::<Position>("position".into())
+            .unwrap()
+            .x = 50;
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(received(&server), 10);
+    }
+
+    #[test]
+    fn updates_after_a_clamp_are_sent_in_full() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        resources.register_inbound_with(identifier(), Position::new(0), XorDelta::new());
+        server.register_validator("position".into(), |_, _, new: &Position| {
+            Verdict::Clamp(Position::new(new.x.min(10)))
+        });
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_outbound_with("position".into(), Position::new(0), XorDelta::new());
+        testing::connect(&mut server, &mut client);
+
+        let set = |client: &mut TestClient, x: i32| {
+            let resources = client.resources_mut();
+            resources
+                .outbound_mut::<Position>("position".into())
+                .unwrap()
+                .x = x;
+        };
+        set(&mut client, 50);
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(received(&server), 10);
+
+        // A diff from 50 to 9 applied to the clamped 10 would give 49.
+        set(&mut client, 9);
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(received(&server), 9);
+        set(&mut client, 8);
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(received(&server), 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "another type")]
+    fn validator_of_another_type_is_refused() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        resources.register_inbound_with(identifier(), Position::new(0), XorDelta::new());
+        server.register_validator("position".into(), |_, _, _: &u32| Verdict::Accept);
+    }
+
+    #[test]
+    fn validator_of_another_type_rejects_instead_of_panicking() {
+        let mut server = testing::server();
+        server.register_validator("position".into(), |_, _, _: &u32| Verdict::Accept);
+        let resources = server.resources_mut();
+        resources.register_inbound_with(identifier(), Position::new(0), XorDelta::new());
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_outbound_with("position".into(), Position::new(0), XorDelta::new());
+        testing::connect(&mut server, &mut client);
+
+        client
+            .resources_mut()
+            .outbound_mut::<Position>("position".into())
+            .unwrap()
+            .x = 5;
+        testing::pump(&mut server, &mut [&mut client]);
+        assert_eq!(received(&server), 0);
+    }
+}
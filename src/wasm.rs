@@ -0,0 +1,112 @@
+use crate::{Reliability, SynchronizeInbound, SynchronizeOutbound};
+use js_sys::{ArrayBuffer, Function, Uint8Array};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+
+// A connection to the server carried by JavaScript, for instance over a WebRTC data channel.
+// Outbound packets are passed to `send` as a `Uint8Array` followed by the index of their
+// reliability, and received ones are pushed back through `push` or `receiver`.
+pub struct JsChannel {
+    send: Function,
+    incoming: Rc<RefCell<VecDeque<Vec<u8>>>>,
+}
+
+impl JsChannel {
+    pub fn new(send: Function) -> Self {
+        Self {
+            send,
+            incoming: Rc::new(RefCell::new(VecDeque::new())),
+        }
+    }
+
+    pub fn push(&self, data: &[u8]) {
+        self.incoming.borrow_mut().push_back(data.to_vec());
+    }
+
+    // A function JavaScript can call with each received `Uint8Array` or `ArrayBuffer`, other
+    // values are ignored. The closure behind it is never freed, so every call leaks one and it
+    // is best called once per channel.
+    pub fn receiver(&self) -> Function {
+        let incoming = Rc::clone(&self.incoming);
+        let receive = Closure::<dyn FnMut(JsValue)>::new(move |data: JsValue| {
+            let data = if let Some(array) = data.dyn_ref::<Uint8Array>() {
+                array.to_vec()
+            } else if let Some(buffer) = data.dyn_ref::<ArrayBuffer>() {
+                Uint8Array::new(buffer).to_vec()
+            } else {
+                return;
+            };
+            incoming.borrow_mut().push_back(data);
+        });
+        receive.into_js_value().unchecked_into()
+    }
+
+    pub fn recv(&mut self) -> Option<Vec<u8>> {
+        self.incoming.borrow_mut().pop_front()
+    }
+}
+
+impl SynchronizeOutbound<()> for JsChannel {
+    fn synchronize(&mut self, bound: (), data: &[u8]) {
+        self.synchronize_with(bound, data, Reliability::ReliableOrdered);
+    }
+
+    fn synchronize_with(&mut self, _bound: (), data: &[u8], reliability: Reliability) {
+        let data = Uint8Array::from(data);
+        let reliability = JsValue::from(reliability as u32);
+        let _ = self.send.call2(&JsValue::NULL, &data, &reliability);
+    }
+}
+
+impl SynchronizeInbound<()> for JsChannel {
+    fn receive(&mut self) -> Option<((), Vec<u8>)> {
+        self.recv().map(|packet| ((), packet))
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use js_sys::Array;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn packets_are_sent_with_their_reliability() {
+        let sent = Array::new();
+        let body = "return (data, reliability) => sent.push([data, reliability])";
+        let push = Function::new_with_args("sent", body).call1(&JsValue::NULL, &sent);
+        let mut channel = JsChannel::new(push.unwrap().unchecked_into());
+        channel.synchronize_with((), &[1, 2], Reliability::Unreliable);
+        channel.synchronize((), &[3]);
+
+        assert_eq!(sent.length(), 2);
+        let packet = |index: u32| {
+            let packet = Array::from(&sent.get(index));
+            let data = Uint8Array::new(&packet.get(0)).to_vec();
+            (data, packet.get(1).as_f64().unwrap() as u32)
+        };
+        assert_eq!(packet(0), (vec![1, 2], Reliability::Unreliable as u32));
+        assert_eq!(packet(1), (vec![3], Reliability::ReliableOrdered as u32));
+    }
+
+    #[wasm_bindgen_test]
+    fn receivers_accept_typed_arrays_and_buffers() {
+        let mut channel = JsChannel::new(Function::new_no_args(""));
+        let receiver = channel.receiver();
+        let array = Uint8Array::from(&[1, 2][..]);
+        receiver.call1(&JsValue::NULL, &array).unwrap();
+        receiver.call1(&JsValue::NULL, &array.buffer()).unwrap();
+        receiver
+            .call1(&JsValue::NULL, &JsValue::from("text"))
+            .unwrap();
+        channel.push(&[3]);
+
+        assert_eq!(channel.receive(), Some(((), vec![1, 2])));
+        assert_eq!(channel.recv(), Some(vec![1, 2]));
+        assert_eq!(channel.recv(), Some(vec![3]));
+        assert_eq!(channel.recv(), None);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::codec::Codec;
+use serde::Serialize;
+use std::ops::Range;
+
+pub(crate) const FULL: u8 = 0;
+pub(crate) const DIFF: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PacketKind {
+    Resource,
+    Message,
+    Request(u32),
+    Response(u32),
+    Handshake,
+    Ack,
+    Nack,
+}
+
+// Header is a kind tag, the call id for requests and responses, then the identifier, all varints.
+// The upper half of the tag holds the compression of the body, which runs to the end of the packet.
+#[derive(Debug)]
+pub(crate) struct ResourcePacket<'a> {
+    pub(crate) kind: PacketKind,
+    pub(crate) compression: u8,
+    pub(crate) identifier: u32,
+    pub(crate) data: &'a [u8],
+}
+
+impl<'a> ResourcePacket<'a> {
+    pub(crate) fn write_header(kind: PacketKind, identifier: u32, buffer: &mut Vec<u8>) {
+        match kind {
+            PacketKind::Resource => buffer.push(0),
+            PacketKind::Message => buffer.push(1),
+            PacketKind::Request(id) => {
+                buffer.push(2);
+                write_varint(id, buffer);
+            }
+            PacketKind::Response(id) => {
+                buffer.push(3);
+                write_varint(id, buffer);
+            }
+            PacketKind::Handshake => buffer.push(4),
+            PacketKind::Ack => buffer.push(5),
+            PacketKind::Nack => buffer.push(6),
+        }
+        write_varint(identifier, buffer);
+    }
+
+    pub(crate) fn write<K, T>(
+        kind: PacketKind,
+        identifier: u32,
+        value: &T,
+        buffer: &mut Vec<u8>,
+    ) -> Range<usize>
+    where
+        K: Codec,
+        T: ?Sized + Serialize,
+    {
+        let start = buffer.len();
+        Self::write_header(kind, identifier, buffer);
+        K::serialize_into(value, buffer).unwrap();
+        start..buffer.len()
+    }
+
+    pub(crate) fn read(data: &'a [u8]) -> Option<Self> {
+        let (tag, mut data) = data.split_first()?;
+        let kind = match tag & 0x0f {
+            0 => PacketKind::Resource,
+            1 => PacketKind::Message,
+            2 => PacketKind::Request(read_varint(&mut data)?),
+            3 => PacketKind::Response(read_varint(&mut data)?),
+            4 => PacketKind::Handshake,
+            5 => PacketKind::Ack,
+            6 => PacketKind::Nack,
+            _ => return None,
+        };
+        let identifier = read_varint(&mut data)?;
+        Some(Self {
+            kind,
+            compression: tag >> 4,
+            identifier,
+            data,
+        })
+    }
+}
+
+// Acknowledges a generation of the resource with the given id, as clients do after applying it.
+#[doc(hidden)]
+pub(crate) fn write_ack(identifier: u32, generation: usize, buffer: &mut Vec<u8>) {
+    ResourcePacket::write_header(PacketKind::Ack, identifier, buffer);
+    write_varint(generation as u32, buffer);
+}
+
+pub(crate) fn write_varint(mut value: u32, buffer: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+pub(crate) fn read_varint(data: &mut &[u8]) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let (byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::DefaultCodec;
+    use crate::diff::FullOnly;
+    use crate::resources::Resources;
+    use crate::testing;
+    use crate::SynchronizeInbound;
+
+    #[test]
+    fn headers_round_trip() {
+        let kinds = [
+            PacketKind::Resource,
+            PacketKind::Message,
+            PacketKind::Request(300),
+            PacketKind::Response(u32::MAX),
+            PacketKind::Handshake,
+            PacketKind::Ack,
+            PacketKind::Nack,
+        ];
+        for kind in kinds.iter().copied() {
+            let mut buffer = Vec::new();
+            ResourcePacket::write_header(kind, 129, &mut buffer);
+            buffer.extend_from_slice(&[1, 2, 3]);
+            let packet = ResourcePacket::read(&buffer).unwrap();
+            assert_eq!(packet.kind, kind);
+            assert_eq!(packet.compression, 0);
+            assert_eq!(packet.identifier, 129);
+            assert_eq!(packet.data, &[1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn compression_is_read_from_the_upper_half_of_the_tag() {
+        let packet = ResourcePacket::read(&[0x21, 3]).unwrap();
+        assert_eq!(packet.kind, PacketKind::Message);
+        assert_eq!(packet.compression, 2);
+        assert!(ResourcePacket::read(&[7, 0]).is_none());
+        assert!(ResourcePacket::read(&[2]).is_none());
+        assert!(ResourcePacket::read(&[]).is_none());
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        let values = [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (16_383, 2),
+            (16_384, 3),
+            (u32::MAX, 5),
+        ];
+        for (value, length) in values.iter().copied() {
+            let mut buffer = Vec::new();
+            write_varint(value, &mut buffer);
+            assert_eq!(buffer.len(), length);
+            buffer.push(9);
+            let mut data = &buffer[..];
+            assert_eq!(read_varint(&mut data), Some(value));
+            assert_eq!(data, &[9]);
+        }
+        assert_eq!(read_varint(&mut &[0x80, 0x80][..]), None);
+        assert_eq!(read_varint(&mut &[0xff; 6][..]), None);
+    }
+
+    #[test]
+    fn resource_packets_hold_a_single_encoding() {
+        let mut server = testing::server();
+        let resources = server.resources_mut();
+        resources.register_outbound_with("name".into(), String::new(), FullOnly);
+        let mut client = testing::client(&mut server);
+        let resources = client.resources_mut();
+        resources.register_inbound_with("name".into(), String::new(), FullOnly);
+        testing::connect(&mut server, &mut client);
+        while client.node.node.receive().is_some() {}
+
+        let name = String::from("rayzo");
+        **server
+            .resources_mut()
+            .outbound_mut::<String>("name".into())
+            .unwrap() = name.clone();
+        server.synchronize_outbound();
+        let (_, packet) = client.node.node.receive().unwrap();
+
+        let id = server.resources().schema().id("name").unwrap();
+        let mut expected = Vec::new();
+        ResourcePacket::write_header(PacketKind::Resource, id, &mut expected);
+        expected.push(FULL);
+        write_varint(0, &mut expected);
+        DefaultCodec::serialize_into(&name, &mut expected).unwrap();
+        assert_eq!(packet, expected);
+    }
+}